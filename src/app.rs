@@ -0,0 +1,177 @@
+use std::{error::Error, path::PathBuf, sync::atomic::Ordering};
+
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{get, post, put},
+    Router,
+};
+use keepass::{Database, DatabaseKey};
+use tokio::task::JoinHandle;
+
+use crate::{
+    attachments::AttachmentLimits,
+    audit::{record_event, Actor},
+    config::ServeConfig,
+    db::save_database,
+    feed::FeedTokens,
+    metrics::track_requests,
+    outbox::{spawn_outbox_worker, OutboundConfig},
+    routes::{
+        add_reaction, attachment_usage, category_feed, create_reply, create_thread,
+        dismiss_post_report, events, export_subtree, forum_feed, get_post_attachment,
+        get_thread_detail, healthz, index, integrity, list_categories, list_reports, list_tags,
+        list_threads_in_category, list_threads_with_tag, lock_thread, mark_all_notifications_read,
+        mark_category_read, mark_notification_read, mark_thread_read, metrics,
+        moderate_post_action, moderate_user_action, moderation_audit_log, moderation_queue,
+        moderation_settings, notifications, readyz, remove_reaction, report_post, unlock_thread,
+        unread, unwatch_thread, update_category_moderation, update_thread_tags, verify_audit,
+        vote_on_post, watch_thread,
+    },
+    state::{spawn_file_watcher, AppState},
+};
+
+/// An unlocked forum: its state, its routes and the background tasks
+/// working on it.
+pub struct ForumApp {
+    pub state: AppState,
+    pub router: Router,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ForumApp {
+    /// Start serving an opened database. `base_path` is where the routes
+    /// end up mounted, empty for the root; `unlocked_by` and `detail` go
+    /// into the audit record of the unlock.
+    #[allow(clippy::too_many_arguments)]
+    pub fn start(
+        mut db: Database,
+        db_path: PathBuf,
+        key: DatabaseKey,
+        config: &ServeConfig,
+        read_only: bool,
+        base_path: &str,
+        unlocked_by: &Actor,
+        detail: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        // Unlocking the database with its key is the closest thing to a login.
+        // A read-only instance leaves no trace, as it cannot save.
+        if !read_only {
+            let target = db.root.uuid.to_string();
+            record_event(&mut db, unlocked_by, "unlock", &target, Some(detail));
+            save_database(&db, &db_path, &key)?;
+        }
+
+        let attachment_limits = AttachmentLimits {
+            max_size: config.max_attachment_size,
+            max_per_post: config.max_attachments_per_post,
+            allowed_types: config.attachment_types.clone(),
+            protect: config.protect_attachments,
+        };
+        let body_limit = attachment_limits.body_limit();
+        let feed_tokens = FeedTokens::parse(&config.feed_tokens)?;
+        let outbound = OutboundConfig {
+            smtp_url: config.smtp_url.clone(),
+            email_from: config.email_from.clone(),
+            email_to: config.email_to.clone(),
+            webhook_urls: config.webhook_urls.clone(),
+            redact_bodies: config.redact_external,
+        };
+        let state = AppState::new(
+            db,
+            db_path,
+            key,
+            attachment_limits,
+            feed_tokens,
+            outbound,
+            config.moderators.iter().cloned().collect(),
+            read_only,
+            base_path.to_string(),
+            config.tls.is_some(),
+        );
+
+        let mut tasks = Vec::new();
+        if state.read_only {
+            tasks.push(spawn_file_watcher(state.clone()));
+        } else if state.outbound.is_enabled() {
+            tasks.push(spawn_outbox_worker(state.clone())?);
+        }
+
+        let router = forum_router(&state)
+            .layer(DefaultBodyLimit::max(body_limit))
+            .with_state(state.clone());
+        Ok(Self {
+            state,
+            router,
+            tasks,
+        })
+    }
+
+    /// Stop the background tasks, let a save that is under way finish and
+    /// drop the decrypted content. Requests still running against the
+    /// forum see it empty and can no longer save.
+    pub async fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        let mut db = self.state.db.write().await;
+        self.state.closed.store(true, Ordering::Relaxed);
+        *db = Database::new(Default::default());
+    }
+}
+
+/// Every route of one forum. A read-only instance has no write routes at
+/// all, so they answer 404.
+fn forum_router(state: &AppState) -> Router<AppState> {
+    let mut app = Router::new()
+        .route("/", get(index))
+        .route("/categories", get(list_categories))
+        .route("/categories/:id/threads", get(list_threads_in_category))
+        .route("/categories/:id/feed.atom", get(category_feed))
+        .route("/feed.atom", get(forum_feed))
+        .route("/events", get(events))
+        .route("/threads/:id", get(get_thread_detail))
+        .route("/tags", get(list_tags))
+        .route("/tags/:tag/threads", get(list_threads_with_tag))
+        .route("/me/unread", get(unread))
+        .route("/notifications", get(notifications))
+        .route("/posts/:id/attachments/:name", get(get_post_attachment))
+        .route("/moderation/settings", get(moderation_settings))
+        .route("/moderation/queue", get(moderation_queue))
+        .route("/moderation/reports", get(list_reports))
+        .route("/moderation/audit", get(moderation_audit_log))
+        .route("/admin/audit", get(moderation_audit_log))
+        .route("/admin/audit/verify", get(verify_audit))
+        .route("/admin/attachments", get(attachment_usage))
+        .route("/admin/integrity", get(integrity))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    if !state.read_only {
+        app = app
+            .route("/categories/:id/read", post(mark_category_read))
+            .route("/threads", post(create_thread))
+            .route("/threads/:id/replies", post(create_reply))
+            .route("/threads/:id/read", post(mark_thread_read))
+            .route("/threads/:id/watch", post(watch_thread).delete(unwatch_thread))
+            .route("/threads/:id/tags", put(update_thread_tags))
+            .route("/notifications/read", post(mark_all_notifications_read))
+            .route("/notifications/:id/read", post(mark_notification_read))
+            .route(
+                "/posts/:id/reactions/:kind",
+                post(add_reaction).delete(remove_reaction),
+            )
+            .route("/posts/:id/vote", post(vote_on_post))
+            .route("/posts/:id/report", post(report_post))
+            .route("/moderation/reports/:id/dismiss", post(dismiss_post_report))
+            .route("/moderation/posts/:id/:action", post(moderate_post_action))
+            .route("/moderation/threads/:id/lock", post(lock_thread).delete(unlock_thread))
+            .route("/moderation/users/:name/:action", post(moderate_user_action))
+            .route("/moderation/categories/:id", put(update_category_moderation))
+            .route("/admin/export", post(export_subtree));
+    }
+    app.route_layer(middleware::from_fn_with_state(
+        state.metrics.clone(),
+        track_requests,
+    ))
+}
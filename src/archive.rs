@@ -0,0 +1,365 @@
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+use keepass::{db::Value, Database};
+use mail_parser::{mailbox::mbox::MessageIterator, HeaderValue, MessageParser};
+use serde::Deserialize;
+
+use crate::db::{
+    add_reply_to_thread, add_thread_to_category, find_entry_by_id_mut, find_group_by_id,
+};
+
+/// Custom field naming the archive format a post was imported from.
+pub const IMPORT_SOURCE_FIELD: &str = "import_source";
+/// Custom field holding the post's id in the source archive (e.g. Message-ID).
+pub const ORIGINAL_ID_FIELD: &str = "original_id";
+/// Custom field holding the author as written in the source archive.
+pub const ORIGINAL_AUTHOR_FIELD: &str = "original_author";
+/// Custom field holding the original creation time (RFC 3339).
+pub const ORIGINAL_CREATED_AT_FIELD: &str = "original_created_at";
+
+/// Archive formats understood by `import-archive`.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum ArchiveFormat {
+    /// Unix mbox mailing-list archive, threaded by In-Reply-To/References
+    Mbox,
+    /// Discourse topic JSON (`/t/<id>.json`), a list of topics, or `{"topics": [...]}`
+    Discourse,
+}
+
+impl ArchiveFormat {
+    fn name(self) -> &'static str {
+        match self {
+            ArchiveFormat::Mbox => "mbox",
+            ArchiveFormat::Discourse => "discourse",
+        }
+    }
+}
+
+/// A post read from an external archive.
+pub struct ArchivedPost {
+    pub original_id: String,
+    pub author: String,
+    pub original_author: String,
+    pub created_at: Option<String>,
+    pub body: String,
+}
+
+/// A thread read from an external archive, posts in chronological order.
+pub struct ArchivedThread {
+    pub title: String,
+    pub posts: Vec<ArchivedPost>,
+}
+
+/// Read an archive file in the given format.
+pub fn read_archive(format: ArchiveFormat, path: &Path) -> Result<Vec<ArchivedThread>, String> {
+    match format {
+        ArchiveFormat::Mbox => read_mbox(path),
+        ArchiveFormat::Discourse => read_discourse(path),
+    }
+}
+
+/// Strip any number of leading reply/forward markers from a mail subject.
+fn strip_reply_prefixes(subject: &str) -> &str {
+    let mut s = subject.trim();
+    loop {
+        let lower = s.to_ascii_lowercase();
+        let Some(prefix_len) = [
+            "re:",
+            "fwd:",
+            "fw:",
+            "aw:",
+            "回复:",
+            "回复：",
+            "答复:",
+            "答复：",
+        ]
+        .iter()
+        .find(|p| lower.starts_with(*p))
+        .map(|p| p.len()) else {
+            return s;
+        };
+        s = s[prefix_len..].trim_start();
+    }
+}
+
+fn header_ids(value: &HeaderValue) -> Vec<String> {
+    match value {
+        HeaderValue::Text(t) => vec![t.to_string()],
+        HeaderValue::TextList(list) => list.iter().map(|t| t.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Read an mbox file and group its messages into threads.
+///
+/// A message joins the thread of the first id in its References header,
+/// falling back to In-Reply-To; messages with neither start a new thread.
+pub fn read_mbox(path: &Path) -> Result<Vec<ArchivedThread>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    let parser = MessageParser::default();
+
+    struct Mail {
+        id: String,
+        parents: Vec<String>,
+        subject: String,
+        timestamp: i64,
+        post: ArchivedPost,
+    }
+
+    let mut mails = Vec::new();
+    for (n, raw) in MessageIterator::new(BufReader::new(file)).enumerate() {
+        let raw = raw.map_err(|e| format!("Failed to read mbox: {e}"))?;
+        let Some(msg) = parser.parse(raw.contents()) else {
+            tracing::warn!(message = n + 1, "Skipping unparseable message");
+            continue;
+        };
+
+        let id = msg
+            .message_id()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("mbox-message-{}", n + 1));
+        let mut parents = header_ids(msg.references());
+        parents.extend(header_ids(msg.in_reply_to()));
+
+        let (name, address) = msg
+            .from()
+            .and_then(|f| f.first())
+            .map(|a| {
+                (
+                    a.name().unwrap_or("").to_string(),
+                    a.address().unwrap_or("").to_string(),
+                )
+            })
+            .unwrap_or_default();
+        let original_author = match (name.is_empty(), address.is_empty()) {
+            (false, false) => format!("{name} <{address}>"),
+            (false, true) => name.clone(),
+            _ => address.clone(),
+        };
+        let author = if name.is_empty() { address } else { name };
+
+        mails.push(Mail {
+            id: id.clone(),
+            parents,
+            subject: msg.subject().unwrap_or("").to_string(),
+            timestamp: msg.date().map(|d| d.to_timestamp()).unwrap_or(0),
+            post: ArchivedPost {
+                original_id: id,
+                author,
+                original_author,
+                created_at: msg.date().map(|d| d.to_rfc3339()),
+                body: msg.body_text(0).map(|b| b.to_string()).unwrap_or_default(),
+            },
+        });
+    }
+
+    mails.sort_by_key(|m| m.timestamp);
+
+    let mut thread_of: HashMap<String, usize> = HashMap::new();
+    let mut threads: Vec<ArchivedThread> = Vec::new();
+    for mail in mails {
+        let existing = mail
+            .parents
+            .iter()
+            .find_map(|parent| thread_of.get(parent).copied());
+        let idx = match existing {
+            Some(idx) => idx,
+            None => {
+                let title = strip_reply_prefixes(&mail.subject);
+                threads.push(ArchivedThread {
+                    title: if title.is_empty() {
+                        "(no subject)".to_string()
+                    } else {
+                        title.to_string()
+                    },
+                    posts: Vec::new(),
+                });
+                threads.len() - 1
+            }
+        };
+        // Later replies may reference any ancestor, so remember all of them.
+        for parent in &mail.parents {
+            thread_of.entry(parent.clone()).or_insert(idx);
+        }
+        thread_of.insert(mail.id, idx);
+        threads[idx].posts.push(mail.post);
+    }
+
+    Ok(threads)
+}
+
+#[derive(Deserialize)]
+struct DiscourseTopic {
+    title: String,
+    post_stream: DiscoursePostStream,
+}
+
+#[derive(Deserialize)]
+struct DiscoursePostStream {
+    posts: Vec<DiscoursePost>,
+}
+
+#[derive(Deserialize)]
+struct DiscoursePost {
+    id: serde_json::Value,
+    #[serde(default)]
+    post_number: u64,
+    username: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    raw: Option<String>,
+    #[serde(default)]
+    cooked: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DiscourseExport {
+    Topic(DiscourseTopic),
+    Topics(Vec<DiscourseTopic>),
+    Wrapped { topics: Vec<DiscourseTopic> },
+}
+
+/// Crude HTML to text conversion for Discourse `cooked` bodies.
+fn html_to_text(html: &str) -> String {
+    let with_breaks = html
+        .replace("<br>", "\n")
+        .replace("<br/>", "\n")
+        .replace("</p>", "\n\n");
+    let mut out = String::with_capacity(with_breaks.len());
+    let mut in_tag = false;
+    for c in with_breaks.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+/// Read a Discourse topic export.
+pub fn read_discourse(path: &Path) -> Result<Vec<ArchivedThread>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    let export: DiscourseExport = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("Invalid Discourse export: {e}"))?;
+    let topics = match export {
+        DiscourseExport::Topic(t) => vec![t],
+        DiscourseExport::Topics(ts) | DiscourseExport::Wrapped { topics: ts } => ts,
+    };
+
+    Ok(topics
+        .into_iter()
+        .map(|topic| {
+            let mut posts = topic.post_stream.posts;
+            posts.sort_by_key(|p| p.post_number);
+            ArchivedThread {
+                title: topic.title,
+                posts: posts
+                    .into_iter()
+                    .map(|p| {
+                        let body = match (p.raw, p.cooked) {
+                            (Some(raw), _) => raw,
+                            (None, Some(cooked)) => html_to_text(&cooked),
+                            (None, None) => String::new(),
+                        };
+                        let original_author = match &p.name {
+                            Some(name) if !name.is_empty() => format!("{name} (@{})", p.username),
+                            _ => p.username.clone(),
+                        };
+                        ArchivedPost {
+                            original_id: match p.id {
+                                serde_json::Value::String(s) => s,
+                                other => other.to_string(),
+                            },
+                            author: p.username,
+                            original_author,
+                            created_at: p.created_at,
+                            body,
+                        }
+                    })
+                    .collect(),
+            }
+        })
+        .collect())
+}
+
+fn set_import_fields(
+    db: &mut Database,
+    entry_id: &str,
+    format: ArchiveFormat,
+    post: &ArchivedPost,
+) -> Result<(), String> {
+    let entry = find_entry_by_id_mut(&mut db.root, entry_id)
+        .ok_or_else(|| format!("Imported post {entry_id} vanished"))?;
+    let mut fields = vec![
+        (IMPORT_SOURCE_FIELD, format.name().to_string()),
+        (ORIGINAL_ID_FIELD, post.original_id.clone()),
+        (ORIGINAL_AUTHOR_FIELD, post.original_author.clone()),
+    ];
+    if let Some(created_at) = &post.created_at {
+        fields.push((ORIGINAL_CREATED_AT_FIELD, created_at.clone()));
+    }
+    for (key, value) in fields {
+        entry
+            .fields
+            .insert(key.to_string(), Value::Unprotected(value));
+    }
+    Ok(())
+}
+
+/// Add archived threads to a category in memory. The caller saves once afterwards.
+///
+/// Returns the number of threads and posts added.
+pub fn add_archived_threads(
+    db: &mut Database,
+    category_id: &str,
+    format: ArchiveFormat,
+    threads: &[ArchivedThread],
+) -> Result<(usize, usize), String> {
+    let mut thread_count = 0;
+    let mut post_count = 0;
+
+    for thread in threads {
+        let Some((first, replies)) = thread.posts.split_first() else {
+            continue;
+        };
+
+        let thread_id = add_thread_to_category(
+            db,
+            category_id,
+            &thread.title,
+            &first.author,
+            &first.body,
+            &[],
+            &[],
+            false,
+            false,
+        )?;
+        let first_id = find_group_by_id(&db.root, &thread_id)
+            .and_then(|g| g.entries().first().map(|e| e.uuid.to_string()))
+            .ok_or_else(|| "Imported thread vanished".to_string())?;
+        set_import_fields(db, &first_id, format, first)?;
+
+        for reply in replies {
+            let reply_id =
+                add_reply_to_thread(db, &thread_id, &reply.author, &reply.body, &[], false, false)?;
+            set_import_fields(db, &reply_id, format, reply)?;
+        }
+
+        thread_count += 1;
+        post_count += thread.posts.len();
+    }
+
+    Ok((thread_count, post_count))
+}
@@ -0,0 +1,174 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use keepass::db::{Entry, Group, NodeRef, Value};
+
+use crate::dto::AttachmentDto;
+
+/// Custom field prefix holding the base64-encoded attachment content.
+///
+/// The keepass crate drops native `<Binary>` references when parsing, so
+/// attachments are kept as entry fields to survive a load/save round trip.
+pub const ATTACHMENT_FIELD_PREFIX: &str = "Attachment:";
+
+/// Custom field prefix holding the content type of an attachment.
+pub const ATTACHMENT_TYPE_FIELD_PREFIX: &str = "AttachmentType:";
+
+/// A validated upload, ready to be stored on a post entry.
+pub struct NewAttachment {
+    pub name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+    pub protected: bool,
+}
+
+/// Size and type limits applied to uploaded attachments.
+#[derive(Clone, Debug)]
+pub struct AttachmentLimits {
+    pub max_size: usize,
+    pub max_per_post: usize,
+    pub allowed_types: Vec<String>,
+    pub protect: bool,
+}
+
+impl AttachmentLimits {
+    /// Upper bound for a whole request body carrying attachments.
+    pub fn body_limit(&self) -> usize {
+        self.max_size
+            .saturating_mul(self.max_per_post)
+            .saturating_add(1024 * 1024)
+    }
+
+    /// Whether `content_type` matches one of the allowed types.
+    /// Entries ending in `/` (e.g. `image/`) match a whole family.
+    pub fn allows_type(&self, content_type: &str) -> bool {
+        self.allowed_types.iter().any(|allowed| {
+            if allowed.ends_with('/') {
+                content_type.starts_with(allowed.as_str())
+            } else {
+                content_type == allowed
+            }
+        })
+    }
+
+    /// Check one upload against the limits and turn it into a `NewAttachment`.
+    pub fn validate(
+        &self,
+        file_name: &str,
+        content_type: &str,
+        data: Vec<u8>,
+    ) -> Result<NewAttachment, String> {
+        let name = sanitize_file_name(file_name)
+            .ok_or_else(|| format!("Invalid attachment name '{file_name}'"))?;
+        if data.len() > self.max_size {
+            return Err(format!(
+                "Attachment '{name}' is {} bytes, limit is {} bytes",
+                data.len(),
+                self.max_size
+            ));
+        }
+        if !self.allows_type(content_type) {
+            return Err(format!(
+                "Attachment type '{content_type}' is not allowed"
+            ));
+        }
+
+        Ok(NewAttachment {
+            name,
+            content_type: content_type.to_string(),
+            data,
+            protected: self.protect,
+        })
+    }
+}
+
+/// Strip any directory components and control characters from an uploaded file name.
+pub fn sanitize_file_name(raw: &str) -> Option<String> {
+    let base = raw.rsplit(['/', '\\']).next().unwrap_or("");
+    let name: String = base.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Store an attachment on an entry, replacing any attachment with the same name.
+pub fn add_attachment(entry: &mut Entry, attachment: &NewAttachment) {
+    let encoded = STANDARD.encode(&attachment.data);
+    let value = if attachment.protected {
+        Value::Protected(encoded.into())
+    } else {
+        Value::Unprotected(encoded)
+    };
+
+    entry
+        .fields
+        .insert(format!("{ATTACHMENT_FIELD_PREFIX}{}", attachment.name), value);
+    entry.fields.insert(
+        format!("{ATTACHMENT_TYPE_FIELD_PREFIX}{}", attachment.name),
+        Value::Unprotected(attachment.content_type.clone()),
+    );
+}
+
+/// Read the decoded content and content type of a named attachment.
+pub fn get_attachment(entry: &Entry, name: &str) -> Option<(String, Vec<u8>)> {
+    let encoded = entry.get(&format!("{ATTACHMENT_FIELD_PREFIX}{name}"))?;
+    let data = STANDARD.decode(encoded).ok()?;
+    let content_type = entry
+        .get(&format!("{ATTACHMENT_TYPE_FIELD_PREFIX}{name}"))
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    Some((content_type, data))
+}
+
+/// Decoded size of base64 content, worked out from its length alone.
+fn decoded_len(encoded: &str) -> usize {
+    encoded.trim_end_matches('=').len() * 3 / 4
+}
+
+/// Describe all attachments stored on an entry, sorted by name. The content
+/// is not decoded.
+pub fn list_attachments(entry: &Entry) -> Vec<AttachmentDto> {
+    let mut out: Vec<AttachmentDto> = entry
+        .fields
+        .keys()
+        .filter_map(|key| key.strip_prefix(ATTACHMENT_FIELD_PREFIX))
+        .filter_map(|name| {
+            let encoded = entry.get(&format!("{ATTACHMENT_FIELD_PREFIX}{name}"))?;
+            let content_type = entry
+                .get(&format!("{ATTACHMENT_TYPE_FIELD_PREFIX}{name}"))
+                .unwrap_or("application/octet-stream")
+                .to_string();
+            Some(AttachmentDto {
+                name: name.to_string(),
+                content_type,
+                size: decoded_len(encoded),
+            })
+        })
+        .collect();
+    out.sort_by(|a, b| a.name.cmp(&b.name));
+    out
+}
+
+/// Recursively count attachments and their decoded size under a group,
+/// without decoding them.
+pub fn attachment_usage_in_group(group: &Group) -> (usize, usize) {
+    let mut count = 0;
+    let mut bytes = 0;
+    for node in &group.children {
+        match node.as_ref() {
+            NodeRef::Entry(e) => {
+                for attachment in list_attachments(e) {
+                    count += 1;
+                    bytes += attachment.size;
+                }
+            }
+            NodeRef::Group(g) => {
+                let (c, b) = attachment_usage_in_group(g);
+                count += c;
+                bytes += b;
+            }
+        }
+    }
+    (count, bytes)
+}
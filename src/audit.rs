@@ -0,0 +1,227 @@
+use keepass::{db::Times, Database};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::{set_system_record, system_record, system_records},
+    dto::{AuditHeadDto, AuditRecordDto, AuditVerificationDto},
+};
+
+/// System subgroup holding one entry per audited action, titled with its
+/// zero-padded sequence number, whose notes are the JSON `AuditRecordDto`.
+///
+/// Records are only ever appended. Each one carries the hash of its
+/// predecessor, so editing, reordering or removing a record breaks the
+/// chain from that point on. Removing records from the end breaks it only
+/// against the stored head; whoever also rewrites the head gets past that,
+/// so compare the head hash against one noted down earlier as well.
+pub const AUDIT_GROUP: &str = "audit";
+
+/// System subgroup holding the `head` record (JSON `AuditHeadDto`): the
+/// sequence number and hash of the newest audit record.
+pub const AUDIT_HEAD_GROUP: &str = "audit-head";
+
+const HEAD_KEY: &str = "head";
+
+/// `prev_hash` of the first record.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Who performed an action, and from where.
+pub struct Actor {
+    /// User name, or `server` / `cli` for actions taken by the process itself.
+    pub name: String,
+    /// Peer address of the HTTP request, if the action came over HTTP.
+    pub ip: Option<String>,
+}
+
+impl Actor {
+    pub fn new(name: &str, ip: Option<String>) -> Self {
+        let name = match name.trim() {
+            "" => "Anonymous",
+            name => name,
+        };
+        Self {
+            name: name.to_string(),
+            ip,
+        }
+    }
+
+    /// The process itself, e.g. a one-off command run from a shell.
+    pub fn local(name: &str) -> Self {
+        Self::new(name, None)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// SHA-256 over the record's JSON with an empty `hash`, which covers
+/// every other field including `prev_hash`.
+fn record_hash(record: &AuditRecordDto) -> String {
+    let unhashed = AuditRecordDto {
+        hash: String::new(),
+        ..record.clone()
+    };
+    let json = serde_json::to_string(&unhashed).unwrap_or_default();
+    to_hex(&Sha256::digest(json.as_bytes()))
+}
+
+/// All stored records, oldest first. Records that do not parse are skipped
+/// here and reported by `verify_audit_log`.
+fn stored_records(db: &Database) -> Vec<AuditRecordDto> {
+    let mut records: Vec<AuditRecordDto> = system_records(db, AUDIT_GROUP)
+        .into_iter()
+        .filter_map(|(_, notes)| serde_json::from_str(notes).ok())
+        .collect();
+    records.sort_by_key(|r| r.seq);
+    records
+}
+
+/// Sequence number and hash of the newest record. Databases written before
+/// the head was stored fall back to reading the log once.
+fn chain_head(db: &Database) -> Option<AuditHeadDto> {
+    if let Some(head) = system_record(db, AUDIT_HEAD_GROUP, HEAD_KEY)
+        .and_then(|notes| serde_json::from_str(notes).ok())
+    {
+        return Some(head);
+    }
+    stored_records(db).pop().map(|r| AuditHeadDto {
+        seq: r.seq,
+        hash: r.hash,
+    })
+}
+
+/// Append an action to the audit log.
+///
+/// `target` is the UUID acted on (or a user name for actions on users);
+/// `detail` is free text such as a moderator's reason. Call it before the
+/// change is saved so the record is persisted together with it.
+pub fn record_event(
+    db: &mut Database,
+    actor: &Actor,
+    action: &str,
+    target: &str,
+    detail: Option<&str>,
+) {
+    let head = chain_head(db);
+    let mut record = AuditRecordDto {
+        seq: head.as_ref().map_or(1, |h| h.seq + 1),
+        at: Times::now().format(TIME_FORMAT).to_string(),
+        actor: actor.name.clone(),
+        ip: actor.ip.clone(),
+        action: action.to_string(),
+        target: target.to_string(),
+        detail: detail.map(str::to_string),
+        prev_hash: head.map_or_else(|| GENESIS_HASH.to_string(), |h| h.hash),
+        hash: String::new(),
+    };
+    record.hash = record_hash(&record);
+    let notes = serde_json::to_string(&record).unwrap_or_default();
+    set_system_record(db, AUDIT_GROUP, &format!("{:010}", record.seq), notes);
+    let head = AuditHeadDto {
+        seq: record.seq,
+        hash: record.hash,
+    };
+    let notes = serde_json::to_string(&head).unwrap_or_default();
+    set_system_record(db, AUDIT_HEAD_GROUP, HEAD_KEY, notes);
+}
+
+/// The audit log, newest first, optionally only the newest `limit` records.
+///
+/// Keys are zero-padded sequence numbers, so only the records returned are
+/// parsed.
+pub fn audit_log(db: &Database, limit: Option<usize>) -> Vec<AuditRecordDto> {
+    let mut stored = system_records(db, AUDIT_GROUP);
+    stored.sort_by(|a, b| b.0.cmp(a.0));
+    stored
+        .into_iter()
+        .filter_map(|(_, notes)| serde_json::from_str(notes).ok())
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// Check the whole chain: every record parses, sequence numbers run from 1
+/// without gaps, each record links to its predecessor, its hash matches
+/// its content and the last one is the stored head.
+///
+/// `known_head` is a hash noted down earlier (e.g. a previous `head_hash`);
+/// it must still be part of the chain, which shows nothing up to that record
+/// was removed or rewritten since.
+pub fn verify_audit_log(db: &Database, known_head: Option<&str>) -> AuditVerificationDto {
+    let stored = system_records(db, AUDIT_GROUP);
+    let mut out = AuditVerificationDto {
+        ok: true,
+        records: stored.len(),
+        head_seq: 0,
+        head_hash: GENESIS_HASH.to_string(),
+        broken_at: None,
+        error: None,
+    };
+
+    let mut seen_known_head = known_head.is_none_or(|h| h == GENESIS_HASH);
+    let mut records = Vec::with_capacity(stored.len());
+    for (key, notes) in stored {
+        match serde_json::from_str::<AuditRecordDto>(notes) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                out.ok = false;
+                out.broken_at = key.parse().ok();
+                out.error = Some(format!("Record '{key}' is not valid: {e}"));
+                return out;
+            }
+        }
+    }
+    records.sort_by_key(|r| r.seq);
+
+    for record in &records {
+        let problem = if record.seq != out.head_seq + 1 {
+            Some(format!(
+                "Expected record {} but found {}",
+                out.head_seq + 1,
+                record.seq
+            ))
+        } else if record.prev_hash != out.head_hash {
+            Some(format!(
+                "Record {} does not link to the record before it",
+                record.seq
+            ))
+        } else if record.hash != record_hash(record) {
+            Some(format!(
+                "Record {} was modified after it was written",
+                record.seq
+            ))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            out.ok = false;
+            out.broken_at = Some(record.seq);
+            out.error = Some(problem);
+            return out;
+        }
+        out.head_seq = record.seq;
+        out.head_hash = record.hash.clone();
+        seen_known_head |= known_head == Some(record.hash.as_str());
+    }
+
+    let head = system_record(db, AUDIT_HEAD_GROUP, HEAD_KEY);
+    let head: Option<AuditHeadDto> = head.and_then(|notes| serde_json::from_str(notes).ok());
+    if let Some(head) = head
+        && (head.seq != out.head_seq || head.hash != out.head_hash)
+    {
+        out.ok = false;
+        out.error = Some(format!(
+            "The log ends at record {} but its head is record {}",
+            out.head_seq, head.seq
+        ));
+        return out;
+    }
+
+    if !seen_known_head {
+        out.ok = false;
+        out.error = Some("The known head hash is no longer part of the log".to_string());
+    }
+    out
+}
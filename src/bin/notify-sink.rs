@@ -0,0 +1,134 @@
+//! Local stand-in for an SMTP relay and a webhook receiver.
+//!
+//! Point kdbx-forum at it with `--smtp-url smtp://127.0.0.1:2525` and
+//! `--webhook-url http://127.0.0.1:8025/hook`. Every received message is
+//! printed to stdout as one JSON line, so scripts and tests can assert on it.
+
+use std::error::Error;
+
+use axum::{
+    body::Bytes,
+    http::{Method, StatusCode, Uri},
+    Router,
+};
+use clap::Parser;
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Fake SMTP/HTTP sink for testing outbound notifications.
+#[derive(Parser, Debug)]
+#[command(name = "notify-sink")]
+struct Args {
+    /// Address to accept SMTP on
+    #[arg(long, default_value = "127.0.0.1:2525")]
+    smtp: String,
+
+    /// Address to accept HTTP requests on
+    #[arg(long, default_value = "127.0.0.1:8025")]
+    http: String,
+
+    /// Status code to answer HTTP requests with, e.g. 503 to exercise retries
+    #[arg(long, default_value_t = 204)]
+    http_status: u16,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let status = StatusCode::from_u16(args.http_status)?;
+
+    let smtp = TcpListener::bind(&args.smtp).await?;
+    let http = TcpListener::bind(&args.http).await?;
+    eprintln!(
+        "notify-sink: SMTP on {}, HTTP on {}",
+        args.smtp, args.http
+    );
+
+    tokio::spawn(async move {
+        loop {
+            match smtp.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = serve_smtp(stream).await {
+                            eprintln!("notify-sink: SMTP session failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => eprintln!("notify-sink: accept failed: {e}"),
+            }
+        }
+    });
+
+    let app = Router::new().fallback(move |method: Method, uri: Uri, body: Bytes| async move {
+        println!(
+            "{}",
+            json!({
+                "channel": "http",
+                "method": method.as_str(),
+                "path": uri.to_string(),
+                "body": String::from_utf8_lossy(&body),
+            })
+        );
+        status
+    });
+    axum::serve(http, app).await?;
+    Ok(())
+}
+
+/// Speak just enough SMTP to accept messages from a well-behaved client.
+async fn serve_smtp(stream: TcpStream) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    write.write_all(b"220 notify-sink ESMTP\r\n").await?;
+
+    let mut from = String::new();
+    let mut to = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        let upper = line.to_ascii_uppercase();
+        let reply: &[u8] = if upper.starts_with("EHLO") || upper.starts_with("HELO") {
+            b"250 notify-sink\r\n"
+        } else if upper.starts_with("MAIL FROM:") {
+            from = line[10..].trim().to_string();
+            b"250 OK\r\n"
+        } else if upper.starts_with("RCPT TO:") {
+            to.push(line[8..].trim().to_string());
+            b"250 OK\r\n"
+        } else if upper == "DATA" {
+            write.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await?;
+            let mut data = Vec::new();
+            while let Some(line) = lines.next_line().await? {
+                if line == "." {
+                    break;
+                }
+                // Undo dot-stuffing.
+                data.push(line.strip_prefix('.').map(str::to_string).unwrap_or(line));
+            }
+            println!(
+                "{}",
+                json!({
+                    "channel": "smtp",
+                    "from": from,
+                    "to": to,
+                    "data": data.join("\n"),
+                })
+            );
+            from.clear();
+            to.clear();
+            b"250 OK: queued\r\n"
+        } else if upper == "QUIT" {
+            write.write_all(b"221 Bye\r\n").await?;
+            return Ok(());
+        } else if upper == "RSET" {
+            from.clear();
+            to.clear();
+            b"250 OK\r\n"
+        } else {
+            b"250 OK\r\n"
+        };
+        write.write_all(reply).await?;
+    }
+    Ok(())
+}
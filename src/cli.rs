@@ -0,0 +1,183 @@
+use std::error::Error;
+
+use clap::ValueEnum;
+use keepass::Database;
+use serde::Serialize;
+
+use crate::{
+    audit::{record_event, Actor},
+    db::{
+        add_reply_to_thread, add_thread_to_category, category_dtos, categories, find_group_by_id,
+        thread_detail, thread_summaries,
+    },
+    export::find_category_of,
+    moderation::{is_banned, is_category_locked, is_thread_locked, requires_approval},
+};
+
+/// How the reading and posting commands print their results.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Tab-separated lines, or a plain rendering of a thread
+    #[default]
+    Text,
+    /// The same JSON the HTTP API returns
+    Json,
+}
+
+fn print_json(value: &impl Serialize) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+pub fn print_categories(db: &Database, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    let categories = category_dtos(db);
+    match format {
+        OutputFormat::Json => print_json(&categories)?,
+        OutputFormat::Text => {
+            for category in categories {
+                println!(
+                    "{}\t{}{}\t{}",
+                    category.id,
+                    category.name,
+                    if category.locked { " (locked)" } else { "" },
+                    category.description.unwrap_or_default()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn print_threads(
+    db: &Database,
+    category_id: &str,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let category = categories(&db.root)
+        .find(|c| c.uuid.to_string() == category_id)
+        .ok_or("Category not found")?;
+    let threads = thread_summaries(category);
+    match format {
+        OutputFormat::Json => print_json(&threads)?,
+        OutputFormat::Text => {
+            for thread in threads {
+                println!(
+                    "{}\t{} posts\t{}\t{}",
+                    thread.id,
+                    thread.post_count,
+                    thread.title,
+                    thread.tags.join(",")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print a thread with every post, pending and hidden ones included: whoever
+/// holds the key sees what moderators see.
+pub fn print_thread(
+    db: &Database,
+    thread_id: &str,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let category = find_category_of(&db.root, thread_id)
+        .filter(|c| c.uuid.to_string() != thread_id)
+        .ok_or("Thread not found")?;
+    let thread = find_group_by_id(category, thread_id).ok_or("Thread not found")?;
+    let mut detail = thread_detail(thread, None, true);
+    detail.category_locked = is_category_locked(db, &category.uuid.to_string());
+    match format {
+        OutputFormat::Json => print_json(&detail)?,
+        OutputFormat::Text => {
+            println!("# {}", detail.title);
+            if !detail.tags.is_empty() {
+                println!("tags: {}", detail.tags.join(", "));
+            }
+            if detail.locked || detail.category_locked {
+                println!("locked");
+            }
+            for post in detail.posts {
+                println!();
+                let status = post.status.map(|s| format!(" [{s}]")).unwrap_or_default();
+                println!("--- {} ({}, score {}){status}", post.author, post.id, post.score);
+                println!("{}", post.body);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the id of a new thread or reply.
+pub fn print_created(id: &str, held: bool, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Json => print_json(&serde_json::json!({ "id": id, "held": held }))?,
+        OutputFormat::Text if held => println!("{id}\t(held for approval)"),
+        OutputFormat::Text => println!("{id}"),
+    }
+    Ok(())
+}
+
+/// Start a thread the way `POST /threads` does, with the same ban and
+/// approval rules. Returns the thread id and whether it was held.
+pub fn post_thread(
+    db: &mut Database,
+    category_id: &str,
+    title: &str,
+    author: &str,
+    body: &str,
+    tags: &[String],
+    moderators: &[String],
+) -> Result<(String, bool), String> {
+    if !categories(&db.root).any(|c| c.uuid.to_string() == category_id) {
+        return Err("Category not found".to_string());
+    }
+    if is_category_locked(db, category_id) {
+        return Err("Category is locked".to_string());
+    }
+    if is_banned(db, author) {
+        return Err(format!("{author} is banned from posting"));
+    }
+    let moderator = moderators.iter().any(|m| m.trim() == author.trim());
+    let held = requires_approval(db, category_id, author, moderator);
+    let thread_id = add_thread_to_category(db, category_id, title, author, body, tags, &[], held, true)?;
+    let detail = format!(
+        "category {category_id}{}",
+        if held { ", held for approval" } else { "" }
+    );
+    record_event(db, &Actor::local(author), "create_thread", &thread_id, Some(&detail));
+    Ok((thread_id, held))
+}
+
+/// Reply the way `POST /threads/:id/replies` does. Returns the post id and
+/// whether it was held.
+pub fn post_reply(
+    db: &mut Database,
+    thread_id: &str,
+    author: &str,
+    body: &str,
+    moderators: &[String],
+) -> Result<(String, bool), String> {
+    let category = find_category_of(&db.root, thread_id)
+        .filter(|c| c.uuid.to_string() != thread_id)
+        .ok_or("Thread not found")?;
+    let category_id = category.uuid.to_string();
+    if find_group_by_id(category, thread_id).is_some_and(is_thread_locked) {
+        return Err("Thread is locked".to_string());
+    }
+    if is_category_locked(db, &category_id) {
+        return Err("Category is locked".to_string());
+    }
+    if is_banned(db, author) {
+        return Err(format!("{author} is banned from posting"));
+    }
+    let moderator = moderators.iter().any(|m| m.trim() == author.trim());
+    let held = requires_approval(db, &category_id, author, moderator);
+    let post_id = add_reply_to_thread(db, thread_id, author, body, &[], held, true)?;
+    let detail = format!(
+        "thread {thread_id}{}",
+        if held { ", held for approval" } else { "" }
+    );
+    record_event(db, &Actor::local(author), "create_reply", &post_id, Some(&detail));
+    Ok((post_id, held))
+}
@@ -0,0 +1,231 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{args::ServeArgs, forums::ForumConfig, logging::LogFormat, tls::TlsOptions};
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:3000";
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_MAX_ATTACHMENT_SIZE: usize = 5 * 1024 * 1024;
+const DEFAULT_MAX_ATTACHMENTS_PER_POST: usize = 8;
+const DEFAULT_ATTACHMENT_TYPES: [&str; 6] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "text/plain",
+    "application/pdf",
+];
+const DEFAULT_EMAIL_FROM: &str = "kdbx-forum <kdbx-forum@localhost>";
+
+/// Settings read from the TOML file given with `--config`.
+///
+/// Keys are the long command-line flags with `-` turned into `_`, e.g.
+/// `max_attachment_size = 1048576` or `moderators = ["alice"]`. Every key is
+/// optional. Command-line flags win over environment variables
+/// (`KDBX_FORUM_<KEY>`), which win over the file, which wins over the
+/// built-in defaults. Relative paths are taken relative to the file.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub database: Option<PathBuf>,
+    pub keyfile: Option<PathBuf>,
+    /// Better left out and given through `KDBX_FORUM_PASSWORD` or the prompt.
+    pub password: Option<String>,
+
+    pub log_level: Option<String>,
+    pub log_format: Option<LogFormat>,
+    pub log_content: Option<bool>,
+
+    /// Directory `backup` writes to when no output is given.
+    pub backup_dir: Option<PathBuf>,
+
+    pub listen: Option<String>,
+    pub read_only: Option<bool>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    pub http_redirect: Option<String>,
+
+    pub max_attachment_size: Option<usize>,
+    pub max_attachments_per_post: Option<usize>,
+    pub attachment_types: Option<Vec<String>>,
+    pub protect_attachments: Option<bool>,
+
+    pub feed_tokens: Option<Vec<String>>,
+    pub smtp_url: Option<String>,
+    pub email_from: Option<String>,
+    pub email_to: Option<Vec<String>>,
+    pub webhook_urls: Option<Vec<String>>,
+    pub redact_external: Option<bool>,
+
+    pub moderators: Option<Vec<String>>,
+    pub trusted_proxies: Option<Vec<String>>,
+
+    /// Forums of a server hosting several, in place of `database`.
+    pub forums: Option<Vec<ForumConfig>>,
+}
+
+impl FileConfig {
+    /// Read and parse a config file; no path means no file.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {e}", path.display()))?;
+        let mut config: Self = toml::from_str(&text)
+            .map_err(|e| format!("Invalid config file {}: {e}", path.display()))?;
+
+        if let Some(base) = path.parent() {
+            for path in [
+                &mut config.database,
+                &mut config.keyfile,
+                &mut config.backup_dir,
+                &mut config.tls_cert,
+                &mut config.tls_key,
+                &mut config.tls_client_ca,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                if path.is_relative() {
+                    *path = base.join(&*path);
+                }
+            }
+            for forum in config.forums.iter_mut().flatten() {
+                for path in [Some(&mut forum.database), forum.keyfile.as_mut()]
+                    .into_iter()
+                    .flatten()
+                {
+                    if path.is_relative() {
+                        *path = base.join(&*path);
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Everything `serve` needs besides the database and its key, with the
+/// command line, environment, config file and defaults merged.
+pub struct ServeConfig {
+    pub listen: String,
+    pub read_only: bool,
+    pub tls: Option<TlsOptions>,
+    pub http_redirect: Option<String>,
+    pub max_attachment_size: usize,
+    pub max_attachments_per_post: usize,
+    pub attachment_types: Vec<String>,
+    pub protect_attachments: bool,
+    pub feed_tokens: Vec<String>,
+    pub smtp_url: Option<String>,
+    pub email_from: String,
+    pub email_to: Vec<String>,
+    pub webhook_urls: Vec<String>,
+    pub redact_external: bool,
+    pub moderators: Vec<String>,
+    /// Peers whose user header is believed; empty to believe every client.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Forums to host instead of a single database.
+    pub forums: Vec<ForumConfig>,
+}
+
+/// A list given on the command line or in the environment replaces the
+/// file's list rather than adding to it.
+fn list(args: Vec<String>, file: Option<Vec<String>>) -> Vec<String> {
+    if args.is_empty() {
+        file.unwrap_or_default()
+    } else {
+        args
+    }
+}
+
+impl ServeConfig {
+    pub fn merge(args: ServeArgs, file: FileConfig) -> Result<Self, String> {
+        let tls = match (args.tls_cert.or(file.tls_cert), args.tls_key.or(file.tls_key)) {
+            (Some(cert), Some(key)) => Some(TlsOptions {
+                cert,
+                key,
+                client_ca: args.tls_client_ca.or(file.tls_client_ca),
+            }),
+            (None, None) => None,
+            _ => return Err("tls_cert and tls_key must be given together".to_string()),
+        };
+        let http_redirect = args.http_redirect.or(file.http_redirect);
+        if tls.is_none() && http_redirect.is_some() {
+            return Err("http_redirect needs tls_cert and tls_key".to_string());
+        }
+
+        // The user header is whatever the client says it is. Only a proxy
+        // that authenticates users and sets it can make moderation safe.
+        let moderators: Vec<String> = list(args.moderators, file.moderators)
+            .iter()
+            .map(|m| m.trim().to_string())
+            .collect();
+        let trusted_proxies = list(args.trusted_proxies, file.trusted_proxies)
+            .iter()
+            .map(|p| {
+                p.trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid trusted proxy address '{p}'"))
+            })
+            .collect::<Result<Vec<IpAddr>, _>>()?;
+        if !moderators.is_empty() && trusted_proxies.is_empty() {
+            return Err("moderators need trusted_proxies: without an authenticating proxy \
+                        setting X-Forum-User, anyone can claim to be a moderator"
+                .to_string());
+        }
+
+        let forums = match args.forums.is_empty() {
+            true => file.forums.unwrap_or_default(),
+            false => args
+                .forums
+                .iter()
+                .map(|spec| ForumConfig::parse(spec))
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(Self {
+            listen: args
+                .listen
+                .or(file.listen)
+                .unwrap_or_else(|| DEFAULT_LISTEN.to_string()),
+            read_only: args.read_only || file.read_only.unwrap_or(false),
+            tls,
+            http_redirect,
+            max_attachment_size: args
+                .max_attachment_size
+                .or(file.max_attachment_size)
+                .unwrap_or(DEFAULT_MAX_ATTACHMENT_SIZE),
+            max_attachments_per_post: args
+                .max_attachments_per_post
+                .or(file.max_attachments_per_post)
+                .unwrap_or(DEFAULT_MAX_ATTACHMENTS_PER_POST),
+            attachment_types: match list(args.attachment_types, file.attachment_types) {
+                types if types.is_empty() => {
+                    DEFAULT_ATTACHMENT_TYPES.map(str::to_string).to_vec()
+                }
+                types => types,
+            },
+            protect_attachments: args.protect_attachments
+                || file.protect_attachments.unwrap_or(false),
+            feed_tokens: list(args.feed_tokens, file.feed_tokens),
+            smtp_url: args.smtp_url.or(file.smtp_url),
+            email_from: args
+                .email_from
+                .or(file.email_from)
+                .unwrap_or_else(|| DEFAULT_EMAIL_FROM.to_string()),
+            email_to: list(args.email_to, file.email_to),
+            webhook_urls: list(args.webhook_urls, file.webhook_urls),
+            redact_external: args.redact_external || file.redact_external.unwrap_or(false),
+            moderators,
+            trusted_proxies,
+            forums,
+        })
+    }
+}
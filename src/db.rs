@@ -1,21 +1,40 @@
-use std::{error::Error, fs::File, path::PathBuf};
+use std::{error::Error, fs::File, path::PathBuf, time::SystemTime};
 
 use keepass::{
-    db::{Entry, Group, Node, NodeRef, Value},
+    db::{Entry, Group, Node, NodeRef, Times, Value},
     Database, DatabaseKey,
 };
 use rpassword::prompt_password;
+use tracing::instrument;
 
-use crate::dto::PostDto;
+use crate::{
+    attachments::{add_attachment, list_attachments, NewAttachment},
+    dto::{CategoryDto, PostDto, ThreadDetailDto, ThreadSummaryDto},
+    moderation::{
+        can_see, hold_post, is_category_locked, is_published, is_thread_locked, post_status,
+    },
+    notifications::record_new_post,
+    reactions::{reaction_dtos, score, vote_of},
+    tags::{normalize_tags, thread_tags},
+};
 
 /// Build a DatabaseKey from optional password and keyfile path.
 pub fn build_db_key(
     password_arg: Option<String>,
     keyfile: &Option<PathBuf>,
+) -> Result<DatabaseKey, Box<dyn Error>> {
+    build_db_key_with_prompt(password_arg, keyfile, "Master password: ")
+}
+
+/// Like build_db_key, but with a custom interactive prompt.
+pub fn build_db_key_with_prompt(
+    password_arg: Option<String>,
+    keyfile: &Option<PathBuf>,
+    prompt: &str,
 ) -> Result<DatabaseKey, Box<dyn Error>> {
     let password = match password_arg {
         Some(p) => p,
-        None => prompt_password("Master password: ")?,
+        None => prompt_password(prompt)?,
     };
 
     let mut key = DatabaseKey::new().with_password(&password);
@@ -27,7 +46,19 @@ pub fn build_db_key(
     Ok(key)
 }
 
+/// Ask for a new password twice until both entries match.
+pub fn prompt_new_password(prompt: &str) -> Result<String, Box<dyn Error>> {
+    loop {
+        let password = prompt_password(prompt)?;
+        if prompt_password("Repeat password: ")? == password {
+            return Ok(password);
+        }
+        eprintln!("Passwords do not match, try again.");
+    }
+}
+
 /// Open and decrypt the KeePass database from disk.
+#[instrument(level = "debug", skip_all, fields(path = %path.display()))]
 pub fn open_database(
     path: &PathBuf,
     key: &DatabaseKey,
@@ -37,12 +68,14 @@ pub fn open_database(
     Ok(db)
 }
 
-/// Recursively count all entries under a group (including nested groups).
+/// Recursively count all published entries under a group (including nested
+/// groups). Posts awaiting approval or hidden by a moderator are left out.
 pub fn count_entries_in_group(group: &Group) -> usize {
     let mut count = 0;
     for node in &group.children {
         match node.as_ref() {
-            NodeRef::Entry(_) => count += 1,
+            NodeRef::Entry(e) if is_published(e) => count += 1,
+            NodeRef::Entry(_) => {}
             NodeRef::Group(g) => count += count_entries_in_group(g),
         }
     }
@@ -50,7 +83,9 @@ pub fn count_entries_in_group(group: &Group) -> usize {
 }
 
 /// Convert an Entry into a PostDto.
-pub fn entry_to_post_dto(entry: &Entry) -> PostDto {
+///
+/// `viewer` is the requesting user, for their own reactions and vote.
+pub fn entry_to_post_dto(entry: &Entry, viewer: Option<&str>) -> PostDto {
     let title = entry.get_title().unwrap_or("").to_string();
     let author = entry.get_username().unwrap_or("").to_string();
     let body = entry.get("Notes").unwrap_or("").to_string();
@@ -60,20 +95,290 @@ pub fn entry_to_post_dto(entry: &Entry) -> PostDto {
         title,
         author,
         body,
+        attachments: list_attachments(entry),
+        reactions: reaction_dtos(entry, viewer),
+        score: score(entry),
+        my_vote: vote_of(entry, viewer),
+        status: post_status(entry).map(str::to_string),
+    }
+}
+
+/// Name of the root-level group holding kdbx-forum's own bookkeeping
+/// (read markers, ...). It is never shown or addressable as forum content.
+pub const SYSTEM_GROUP_NAME: &str = ".kdbx-forum";
+
+/// Whether a group is the reserved system group.
+pub fn is_system_group(group: &Group) -> bool {
+    group.name == SYSTEM_GROUP_NAME
+}
+
+/// Iterate over the top-level categories (root child groups, minus the system group).
+pub fn categories(root: &Group) -> impl Iterator<Item = &Group> {
+    root.children.iter().filter_map(|node| match node.as_ref() {
+        NodeRef::Group(g) if !is_system_group(g) => Some(g),
+        _ => None,
+    })
+}
+
+/// Get a named subgroup of the system group, creating both if missing.
+pub fn system_subgroup_mut<'a>(db: &'a mut Database, name: &str) -> &'a mut Group {
+    let root = &mut db.root;
+    let idx = match root
+        .children
+        .iter()
+        .position(|node| matches!(node, Node::Group(g) if is_system_group(g)))
+    {
+        Some(idx) => idx,
+        None => {
+            root.add_child(Group::new(SYSTEM_GROUP_NAME));
+            root.children.len() - 1
+        }
+    };
+    let Node::Group(system) = &mut root.children[idx] else {
+        unreachable!("system group index always points at a group");
+    };
+
+    let idx = match system
+        .children
+        .iter()
+        .position(|node| matches!(node, Node::Group(g) if g.name == name))
+    {
+        Some(idx) => idx,
+        None => {
+            system.add_child(Group::new(name));
+            system.children.len() - 1
+        }
+    };
+    let Node::Group(sub) = &mut system.children[idx] else {
+        unreachable!("system subgroup index always points at a group");
+    };
+    sub
+}
+
+/// Get a named subgroup of the system group, if it exists.
+pub fn system_subgroup<'a>(db: &'a Database, name: &str) -> Option<&'a Group> {
+    db.root.children.iter().find_map(|node| match node.as_ref() {
+        NodeRef::Group(system) if is_system_group(system) => {
+            system.children.iter().find_map(|node| match node.as_ref() {
+                NodeRef::Group(g) if g.name == name => Some(g),
+                _ => None,
+            })
+        }
+        _ => None,
+    })
+}
+
+/// Notes of the entry titled `key` in a system subgroup, if any.
+///
+/// System subgroups hold one such record per user (or per queued item),
+/// with the notes carrying JSON.
+pub fn system_record<'a>(db: &'a Database, subgroup: &str, key: &str) -> Option<&'a str> {
+    system_subgroup(db, subgroup)?
+        .entries()
+        .into_iter()
+        .find(|e| e.get_title() == Some(key))
+        .map(|e| e.get("Notes").unwrap_or(""))
+}
+
+/// All `(key, notes)` records in a system subgroup.
+pub fn system_records<'a>(db: &'a Database, subgroup: &str) -> Vec<(&'a str, &'a str)> {
+    system_subgroup(db, subgroup)
+        .map(|group| {
+            group
+                .entries()
+                .into_iter()
+                .filter_map(|e| Some((e.get_title()?, e.get("Notes").unwrap_or(""))))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Create or replace the notes of the entry titled `key` in a system subgroup.
+pub fn set_system_record(db: &mut Database, subgroup: &str, key: &str, notes: String) {
+    let group = system_subgroup_mut(db, subgroup);
+    let existing = group.children.iter_mut().find_map(|node| match node {
+        Node::Entry(e) if e.get_title() == Some(key) => Some(e),
+        _ => None,
+    });
+    let entry = match existing {
+        Some(entry) => entry,
+        None => {
+            let mut entry = Entry::new();
+            entry
+                .fields
+                .insert("Title".to_string(), Value::Unprotected(key.to_string()));
+            group.add_child(entry);
+            let Some(Node::Entry(entry)) = group.children.last_mut() else {
+                unreachable!("just added an entry");
+            };
+            entry
+        }
+    };
+    entry
+        .fields
+        .insert("Notes".to_string(), Value::Unprotected(notes));
+    entry.times.set_last_modification(Times::now());
+}
+
+/// Remove the entry titled `key` from a system subgroup, if present.
+pub fn remove_system_record(db: &mut Database, subgroup: &str, key: &str) {
+    system_subgroup_mut(db, subgroup)
+        .children
+        .retain(|node| !matches!(node, Node::Entry(e) if e.get_title() == Some(key)));
+}
+
+/// List all top-level categories (root child groups).
+pub fn category_dtos(db: &Database) -> Vec<CategoryDto> {
+    categories(&db.root)
+        .map(|g| CategoryDto {
+            id: g.uuid.to_string(),
+            name: g.name.clone(),
+            locked: is_category_locked(db, &g.uuid.to_string()),
+            description: g.notes.clone().filter(|n| !n.is_empty()),
+        })
+        .collect()
+}
+
+/// List all threads (child groups) in a category, except those whose
+/// opening post is not published.
+#[instrument(level = "debug", skip_all, fields(category = %category.uuid))]
+pub fn thread_summaries(category: &Group) -> Vec<ThreadSummaryDto> {
+    let mut out = Vec::new();
+    for node in &category.children {
+        if let NodeRef::Group(g) = node.as_ref()
+            && g.entries().first().is_none_or(|e| is_published(e))
+        {
+            out.push(ThreadSummaryDto {
+                id: g.uuid.to_string(),
+                title: g.name.clone(),
+                post_count: count_entries_in_group(g),
+                score: g.entries().first().map_or(0, |e| score(e)),
+                tags: thread_tags(g),
+                unread_posts: None,
+            });
+        }
+    }
+    out
+}
+
+/// Build the full detail of a thread (all posts within the thread group
+/// that `viewer` may see; see `moderation::can_see`).
+#[instrument(level = "debug", skip_all, fields(thread = %thread_group.uuid, moderator))]
+pub fn thread_detail(
+    thread_group: &Group,
+    viewer: Option<&str>,
+    moderator: bool,
+) -> ThreadDetailDto {
+    let mut posts = Vec::new();
+    for node in &thread_group.children {
+        if let NodeRef::Entry(e) = node.as_ref()
+            && can_see(e, viewer, moderator)
+        {
+            posts.push(entry_to_post_dto(e, viewer));
+        }
+    }
+
+    // The opening post is the question, so only replies can be the best answer.
+    let best_answer_id = posts
+        .iter()
+        .skip(1)
+        .filter(|p| p.score > 0 && p.status.is_none())
+        .fold(None::<&PostDto>, |best, p| match best {
+            Some(b) if b.score >= p.score => Some(b),
+            _ => Some(p),
+        })
+        .map(|p| p.id.clone());
+
+    ThreadDetailDto {
+        id: thread_group.uuid.to_string(),
+        title: thread_group.name.clone(),
+        posts,
+        tags: thread_tags(thread_group),
+        best_answer_id,
+        locked: is_thread_locked(thread_group),
+        category_locked: false,
     }
 }
 
 /// Recursively find a group by its UUID (string form) starting from `group`.
+/// The system group and everything below it are skipped.
+#[instrument(level = "debug", skip(group), fields(found))]
 pub fn find_group_by_id<'a>(group: &'a Group, id: &str) -> Option<&'a Group> {
+    let found = find_group(group, id);
+    tracing::Span::current().record("found", found.is_some());
+    found
+}
+
+fn find_group<'a>(group: &'a Group, id: &str) -> Option<&'a Group> {
     if group.uuid.to_string() == id {
         return Some(group);
     }
 
     for node in &group.children {
-        if let NodeRef::Group(g) = node.as_ref() {
-            if let Some(found) = find_group_by_id(g, id) {
-                return Some(found);
+        if let NodeRef::Group(g) = node.as_ref()
+            && !is_system_group(g)
+            && let Some(found) = find_group(g, id)
+        {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+/// Find a thread, a group directly inside a category, by its UUID (string form).
+pub fn find_thread_by_id<'a>(root: &'a Group, id: &str) -> Option<&'a Group> {
+    categories(root).find_map(|category| {
+        category.children.iter().find_map(|node| match node.as_ref() {
+            NodeRef::Group(g) if g.uuid.to_string() == id => Some(g),
+            _ => None,
+        })
+    })
+}
+
+/// Recursively find an entry by its UUID (string form) starting from `group`.
+/// The system group and everything below it are skipped.
+#[instrument(level = "debug", skip(group), fields(found))]
+pub fn find_entry_by_id<'a>(group: &'a Group, id: &str) -> Option<&'a Entry> {
+    let found = find_entry(group, id);
+    tracing::Span::current().record("found", found.is_some());
+    found
+}
+
+fn find_entry<'a>(group: &'a Group, id: &str) -> Option<&'a Entry> {
+    for node in &group.children {
+        match node.as_ref() {
+            NodeRef::Entry(e) if e.uuid.to_string() == id => return Some(e),
+            NodeRef::Group(g) if !is_system_group(g) => {
+                if let Some(found) = find_entry(g, id) {
+                    return Some(found);
+                }
             }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Mutable variant of find_entry_by_id.
+#[instrument(level = "debug", skip(group), fields(found))]
+pub fn find_entry_by_id_mut<'a>(group: &'a mut Group, id: &str) -> Option<&'a mut Entry> {
+    let found = find_entry_mut(group, id);
+    tracing::Span::current().record("found", found.is_some());
+    found
+}
+
+fn find_entry_mut<'a>(group: &'a mut Group, id: &str) -> Option<&'a mut Entry> {
+    for node in &mut group.children {
+        match node {
+            Node::Entry(e) if e.uuid.to_string() == id => return Some(e),
+            Node::Group(g) if !is_system_group(g) => {
+                if let Some(found) = find_entry_mut(g, id) {
+                    return Some(found);
+                }
+            }
+            _ => {}
         }
     }
 
@@ -81,16 +386,24 @@ pub fn find_group_by_id<'a>(group: &'a Group, id: &str) -> Option<&'a Group> {
 }
 
 /// Mutable variant of find_group_by_id.
+#[instrument(level = "debug", skip(group), fields(found))]
 pub fn find_group_by_id_mut<'a>(group: &'a mut Group, id: &str) -> Option<&'a mut Group> {
+    let found = find_group_mut(group, id);
+    tracing::Span::current().record("found", found.is_some());
+    found
+}
+
+fn find_group_mut<'a>(group: &'a mut Group, id: &str) -> Option<&'a mut Group> {
     if group.uuid.to_string() == id {
         return Some(group);
     }
 
     for node in &mut group.children {
-        if let Node::Group(g) = node {
-            if let Some(found) = find_group_by_id_mut(g, id) {
-                return Some(found);
-            }
+        if let Node::Group(g) = node
+            && !is_system_group(g)
+            && let Some(found) = find_group_mut(g, id)
+        {
+            return Some(found);
         }
     }
 
@@ -98,14 +411,23 @@ pub fn find_group_by_id_mut<'a>(group: &'a mut Group, id: &str) -> Option<&'a mu
 }
 
 /// Add a new thread (group + initial post entry) under the given category.
-/// Returns the new thread group's UUID as a string.
+/// The thread's tags are stored on its initial post. With `notify`, mentions
+/// in the body are recorded as notifications and the author watches the
+/// thread, unless the post is `held` for approval. Imports pass `false`, as
+/// their posts are not new. Returns the new thread group's UUID as a string.
+#[allow(clippy::too_many_arguments)]
 pub fn add_thread_to_category(
     db: &mut Database,
     category_id: &str,
     title: &str,
     author: &str,
     body: &str,
+    tags: &[String],
+    attachments: &[NewAttachment],
+    held: bool,
+    notify: bool,
 ) -> Result<String, String> {
+    let tags = normalize_tags(tags)?;
     let category = find_group_by_id_mut(&mut db.root, category_id)
         .ok_or_else(|| "Category not found".to_string())?;
 
@@ -121,30 +443,46 @@ pub fn add_thread_to_category(
     entry
         .fields
         .insert("Notes".to_string(), Value::Unprotected(body.to_string()));
+    for attachment in attachments {
+        add_attachment(&mut entry, attachment);
+    }
+    entry.tags = tags;
+    if held {
+        hold_post(&mut entry);
+    }
 
+    let post_id = entry.uuid.to_string();
     thread_group.add_child(entry);
 
     let thread_id = thread_group.uuid.to_string();
     category.add_child(thread_group);
 
+    if notify && !held {
+        record_new_post(db, &thread_id, &post_id, author, body, true);
+    }
     Ok(thread_id)
 }
 
-/// Add a reply entry to an existing thread group. Returns the new entry UUID.
+/// Add a reply entry to an existing thread group. With `notify`, mentioned
+/// users and the thread's watchers are notified unless the reply is `held`
+/// for approval. Returns the new entry UUID.
+#[allow(clippy::too_many_arguments)]
 pub fn add_reply_to_thread(
     db: &mut Database,
     thread_id: &str,
     author: &str,
     body: &str,
+    attachments: &[NewAttachment],
+    held: bool,
+    notify: bool,
 ) -> Result<String, String> {
     let thread_group = find_group_by_id_mut(&mut db.root, thread_id)
         .ok_or_else(|| "Thread not found".to_string())?;
 
     let mut entry = Entry::new();
-    let title = if body.len() > 40 {
-        format!("Reply: {}...", &body[..40])
-    } else {
-        format!("Reply: {}", body)
+    let title = match body.char_indices().nth(40) {
+        Some((cut, _)) => format!("Reply: {}...", &body[..cut]),
+        None => format!("Reply: {}", body),
     };
 
     entry
@@ -156,22 +494,39 @@ pub fn add_reply_to_thread(
     entry
         .fields
         .insert("Notes".to_string(), Value::Unprotected(body.to_string()));
+    for attachment in attachments {
+        add_attachment(&mut entry, attachment);
+    }
+    if held {
+        hold_post(&mut entry);
+    }
 
     let id = entry.uuid.to_string();
     thread_group.add_child(entry);
 
+    if notify && !held {
+        record_new_post(db, thread_id, &id, author, body, false);
+    }
     Ok(id)
 }
 
+/// Modification time of the database file, to notice changes made by others.
+pub fn file_modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
 /// Persist the current in-memory database back to disk safely using a temporary file + rename.
+#[instrument(level = "debug", skip_all, fields(path = %db_path.display()))]
 pub fn save_database(
     db: &Database,
     db_path: &PathBuf,
     key: &DatabaseKey,
 ) -> Result<(), Box<dyn Error>> {
+    let started = std::time::Instant::now();
     let tmp_path = db_path.with_extension("kdbx.tmp");
     let mut tmp_file = File::create(&tmp_path)?;
     db.save(&mut tmp_file, key.clone())?;
     std::fs::rename(&tmp_path, db_path)?;
+    tracing::debug!(elapsed_ms = started.elapsed().as_millis() as u64, "saved database");
     Ok(())
 }
@@ -1,10 +1,17 @@
-use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
 
 /// Top-level category (first-level group under root).
 #[derive(Serialize)]
 pub struct CategoryDto {
     pub id: String,
     pub name: String,
+    /// Closed to new threads and replies by a moderator.
+    pub locked: bool,
+    /// The group's notes, e.g. set from an `init` template.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 /// Summary info about a thread within a category.
@@ -13,6 +20,37 @@ pub struct ThreadSummaryDto {
     pub id: String,
     pub title: String,
     pub post_count: usize,
+    /// Vote score of the opening post.
+    pub score: i64,
+    pub tags: Vec<String>,
+    /// Posts the requesting user has not read yet; absent for anonymous requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unread_posts: Option<usize>,
+}
+
+/// Unread counts of one category for a user.
+#[derive(Serialize)]
+pub struct CategoryUnreadDto {
+    pub id: String,
+    pub name: String,
+    pub unread_threads: usize,
+    pub unread_posts: usize,
+}
+
+/// A thread with posts the user has not read yet.
+#[derive(Serialize)]
+pub struct UnreadThreadDto {
+    pub id: String,
+    pub category_id: String,
+    pub title: String,
+    pub unread_posts: usize,
+}
+
+/// Everything a user has not read yet.
+#[derive(Serialize)]
+pub struct UnreadDto {
+    pub categories: Vec<CategoryUnreadDto>,
+    pub threads: Vec<UnreadThreadDto>,
 }
 
 /// A single post (entry) inside a thread.
@@ -22,6 +60,49 @@ pub struct PostDto {
     pub title: String,
     pub author: String,
     pub body: String,
+    pub attachments: Vec<AttachmentDto>,
+    pub reactions: Vec<ReactionDto>,
+    /// Up votes minus down votes.
+    pub score: i64,
+    /// The requesting user's vote: 1, -1, or 0 for none.
+    pub my_vote: i8,
+    /// `pending` (awaiting approval) or `hidden`; absent for published posts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+}
+
+/// How often a post got one kind of reaction.
+#[derive(Serialize)]
+pub struct ReactionDto {
+    pub kind: String,
+    pub emoji: String,
+    pub count: usize,
+    pub reacted_by_me: bool,
+}
+
+/// Metadata about a file attached to a post.
+#[derive(Serialize)]
+pub struct AttachmentDto {
+    pub name: String,
+    pub content_type: String,
+    pub size: usize,
+}
+
+/// Attachment storage used by a single category.
+#[derive(Serialize)]
+pub struct CategoryAttachmentUsageDto {
+    pub id: String,
+    pub name: String,
+    pub count: usize,
+    pub total_bytes: usize,
+}
+
+/// Attachment storage across the whole database.
+#[derive(Serialize)]
+pub struct AttachmentUsageDto {
+    pub count: usize,
+    pub total_bytes: usize,
+    pub categories: Vec<CategoryAttachmentUsageDto>,
 }
 
 /// Full thread detail with all posts.
@@ -30,5 +111,346 @@ pub struct ThreadDetailDto {
     pub id: String,
     pub title: String,
     pub posts: Vec<PostDto>,
+    pub tags: Vec<String>,
+    /// The reply with the highest positive score, if any.
+    pub best_answer_id: Option<String>,
+    /// Locked threads accept no new replies.
+    pub locked: bool,
+    /// The whole category is locked, which also stops replies.
+    pub category_locked: bool,
+}
+
+/// How many threads carry a tag.
+#[derive(Serialize)]
+pub struct TagCountDto {
+    pub tag: String,
+    pub count: usize,
+}
+
+/// A thread found by tag, with the category it lives in.
+#[derive(Serialize)]
+pub struct TaggedThreadDto {
+    pub category_id: String,
+    pub category_name: String,
+    #[serde(flatten)]
+    pub thread: ThreadSummaryDto,
+}
+
+/// Version of the JSON dump format produced by `dump` and read by `restore`.
+/// Version 2 added child order, binary fields, icons, colours and custom data.
+pub const DUMP_SCHEMA_VERSION: u32 = 2;
+
+/// Dump of the whole forum tree: every group and entry with all of their
+/// fields, history, icons, colours and custom data, in their original order.
+/// Auto-type settings and database metadata other than the name and the
+/// custom icons are not part of it.
+#[derive(Serialize, Deserialize)]
+pub struct ForumDumpDto {
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_name: Option<String>,
+    /// Custom icon images by UUID, base64-encoded.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom_icons: BTreeMap<String, String>,
+    pub root: GroupDumpDto,
+}
+
+/// A group (root, category, thread or nested group) in a dump.
+#[derive(Serialize, Deserialize)]
+pub struct GroupDumpDto {
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub times: TimesDumpDto,
+    #[serde(flatten)]
+    pub look: LookDumpDto,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupDumpDto>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub posts: Vec<PostDumpDto>,
+    /// Ids of all children in their original order, when groups and posts
+    /// were mixed rather than groups first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<String>,
+}
+
+/// A post (entry) in a dump, including every field and its history.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PostDumpDto {
+    pub id: String,
+    pub title: String,
+    pub author: String,
+    pub body: String,
+    /// All other entry fields (custom fields, attachments, URL, ...).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, FieldDumpDto>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub times: TimesDumpDto,
+    #[serde(flatten)]
+    pub look: LookDumpDto,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub foreground_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub override_url: Option<String>,
+    /// Previous versions of the post, newest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<PostDumpDto>,
+}
+
+/// A single entry field value.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FieldDumpDto {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+    /// `value` is base64, for binary fields and anything else not UTF-8.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub base64: bool,
+}
+
+/// Icon and custom data, kept for KeePassXC and plugins; the forum itself
+/// only reads its own custom data.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct LookDumpDto {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon_id: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_icon: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom_data: BTreeMap<String, CustomDataDumpDto>,
+}
+
+/// One custom data item of a group or entry.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CustomDataDumpDto {
+    #[serde(flatten)]
+    pub value: FieldDumpDto,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+}
+
+/// KeePass timestamps (UTC, `YYYY-MM-DDTHH:MM:SSZ`) keyed by their KDBX tag name.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct TimesDumpDto {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub expires: bool,
+    #[serde(flatten)]
+    pub times: BTreeMap<String, String>,
+}
+
+/// One line of an NDJSON dump: a header followed by groups and posts in tree order.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DumpRecordDto {
+    Header {
+        schema_version: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        database_name: Option<String>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        custom_icons: BTreeMap<String, String>,
+    },
+    Group {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent_id: Option<String>,
+        #[serde(flatten)]
+        group: GroupDumpDto,
+    },
+    Post {
+        parent_id: String,
+        #[serde(flatten)]
+        post: PostDumpDto,
+    },
+}
+
+/// A notification in a user's inbox. Also the stored form inside the KDBX.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NotificationDto {
+    pub id: String,
+    /// `mention`, `reply` or `warning`.
+    pub kind: String,
+    pub thread_id: String,
+    pub thread_title: String,
+    pub post_id: String,
+    pub author: String,
+    pub created_at: String,
+    #[serde(default)]
+    pub read: bool,
+    /// Free text, e.g. the reason given with a moderator's warning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
 }
 
+/// A user's notification inbox and watched threads.
+#[derive(Serialize)]
+pub struct NotificationsDto {
+    pub unread: usize,
+    pub watching: Vec<String>,
+    pub notifications: Vec<NotificationDto>,
+}
+
+/// Moderation settings of the forum. Also the stored form inside the KDBX.
+#[derive(Default, Serialize, Deserialize)]
+pub struct ModerationSettingsDto {
+    /// Categories where posts by untrusted users wait for approval.
+    #[serde(default)]
+    pub approval_categories: BTreeSet<String>,
+    /// Categories that take no new threads or replies.
+    #[serde(default)]
+    pub locked_categories: BTreeSet<String>,
+    /// Users whose posts never need approval.
+    #[serde(default)]
+    pub trusted_users: BTreeSet<String>,
+    /// Users who may not post or report.
+    #[serde(default)]
+    pub banned_users: BTreeSet<String>,
+    /// Number of warnings each user has received.
+    #[serde(default)]
+    pub warnings: BTreeMap<String, u32>,
+}
+
+/// Moderation settings together with the configured moderators.
+#[derive(Serialize)]
+pub struct ModerationOverviewDto {
+    pub moderators: Vec<String>,
+    #[serde(flatten)]
+    pub settings: ModerationSettingsDto,
+}
+
+/// A user's report about a post. Also the stored form inside the KDBX.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReportDto {
+    pub id: String,
+    pub post_id: String,
+    pub thread_id: String,
+    pub thread_title: String,
+    pub reporter: String,
+    pub reason: String,
+    pub created_at: String,
+    /// How the report was handled, e.g. `hide` or `dismiss`; absent while open.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_by: Option<String>,
+}
+
+/// A post waiting for approval, with the thread it belongs to.
+#[derive(Serialize)]
+pub struct QueuedPostDto {
+    pub category_id: String,
+    pub thread_id: String,
+    pub thread_title: String,
+    pub post: PostDto,
+}
+
+/// An audited action. Also the stored form inside the KDBX.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuditRecordDto {
+    /// Position in the log, starting at 1.
+    pub seq: u64,
+    pub at: String,
+    /// User name, or `server` / `cli` for the process itself.
+    pub actor: String,
+    /// Peer address of the request, for actions taken over HTTP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    pub action: String,
+    /// UUID of the post, thread, category or database acted on, or a user name.
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Hash of the previous record (all zeros for the first one).
+    pub prev_hash: String,
+    /// SHA-256 (hex) of this record with an empty `hash`.
+    pub hash: String,
+}
+
+/// The newest audit record, stored on its own so appending a record does
+/// not have to read the whole log.
+#[derive(Serialize, Deserialize)]
+pub struct AuditHeadDto {
+    pub seq: u64,
+    pub hash: String,
+}
+
+/// Outcome of checking the audit log's hash chain.
+#[derive(Serialize)]
+pub struct AuditVerificationDto {
+    pub ok: bool,
+    pub records: usize,
+    /// Last record that verified, 0 if none did.
+    pub head_seq: u64,
+    pub head_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broken_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A user and their standing, as listed by `kdbx-forum user list`.
+#[derive(Serialize)]
+pub struct UserStandingDto {
+    pub name: String,
+    pub posts: usize,
+    pub moderator: bool,
+    pub trusted: bool,
+    pub banned: bool,
+    pub warnings: u32,
+}
+
+/// Answer of `GET /readyz`.
+#[derive(Serialize)]
+pub struct ReadinessDto {
+    pub ready: bool,
+    /// Whether the database lock could be taken in time.
+    pub database_unlocked: bool,
+    pub last_save_ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_save_error: Option<String>,
+}
+
+/// An entry that sits where only groups belong (the root or a category).
+#[derive(Clone, Serialize)]
+pub struct OrphanEntryDto {
+    pub id: String,
+    pub parent_id: String,
+}
+
+/// Outcome of `GET /admin/integrity`. Nodes are listed by UUID only.
+#[derive(Clone, Serialize)]
+pub struct IntegrityReportDto {
+    pub ok: bool,
+    /// Whether the file on disk could be opened with the server's key.
+    pub disk_readable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_error: Option<String>,
+    /// Groups and entries in memory but not in the file.
+    pub missing_on_disk: Vec<String>,
+    /// Groups and entries in the file but not in memory.
+    pub missing_in_memory: Vec<String>,
+    /// Groups and entries whose content or position differs between the two.
+    pub changed: Vec<String>,
+    pub orphan_entries: Vec<OrphanEntryDto>,
+    /// Thread groups without any post.
+    pub empty_threads: Vec<String>,
+    /// UUIDs used by more than one group or entry.
+    pub duplicate_uuids: Vec<String>,
+}
+
+/// One forum as listed by `GET /forums` on a server hosting several.
+#[derive(Serialize)]
+pub struct ForumSummaryDto {
+    pub name: String,
+    /// Where the forum is served, e.g. `/f/team`.
+    pub path: String,
+    pub unlocked: bool,
+    pub read_only: bool,
+    /// Name of the database's root group; only known while unlocked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
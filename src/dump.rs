@@ -0,0 +1,436 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Write},
+    str::FromStr,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::NaiveDateTime;
+use keepass::{
+    config::DatabaseConfig,
+    db::{Color, CustomData, CustomDataItem, Entry, Group, History, Icon, NodeRef, Times, Value},
+    Database,
+};
+use uuid::Uuid;
+
+use crate::dto::{
+    CustomDataDumpDto, DumpRecordDto, FieldDumpDto, ForumDumpDto, GroupDumpDto, LookDumpDto,
+    PostDumpDto, TimesDumpDto, DUMP_SCHEMA_VERSION,
+};
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Fields that get their own slot in `PostDumpDto` instead of `fields`.
+const STANDARD_POST_FIELDS: [&str; 3] = ["Title", "UserName", "Notes"];
+
+/// Output format of `dump`.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum DumpFormat {
+    /// A single pretty-printed JSON document
+    Json,
+    /// One JSON record per line, suited to line-based diffs
+    Ndjson,
+}
+
+fn times_to_dto(times: &Times) -> TimesDumpDto {
+    TimesDumpDto {
+        expires: times.expires,
+        times: times
+            .times
+            .iter()
+            .map(|(k, v)| (k.clone(), v.format(TIME_FORMAT).to_string()))
+            .collect(),
+    }
+}
+
+fn times_from_dto(dto: &TimesDumpDto) -> Result<Times, String> {
+    let mut times = Times {
+        expires: dto.expires,
+        ..Times::default()
+    };
+    for (k, v) in &dto.times {
+        let parsed = NaiveDateTime::parse_from_str(v, TIME_FORMAT)
+            .map_err(|e| format!("Invalid timestamp {k}='{v}': {e}"))?;
+        times.times.insert(k.clone(), parsed);
+    }
+    Ok(times)
+}
+
+fn value_to_dto(value: &Value) -> FieldDumpDto {
+    let (bytes, protected) = match value {
+        Value::Unprotected(s) => {
+            return FieldDumpDto {
+                value: s.clone(),
+                protected: false,
+                base64: false,
+            };
+        }
+        Value::Protected(p) => (p.unsecure(), true),
+        Value::Bytes(b) => (b.as_slice(), false),
+    };
+    match std::str::from_utf8(bytes) {
+        Ok(text) if protected => FieldDumpDto {
+            value: text.to_string(),
+            protected,
+            base64: false,
+        },
+        _ => FieldDumpDto {
+            value: STANDARD.encode(bytes),
+            protected,
+            base64: true,
+        },
+    }
+}
+
+fn value_from_dto(dto: &FieldDumpDto) -> Result<Value, String> {
+    if !dto.base64 {
+        return Ok(match dto.protected {
+            true => Value::Protected(dto.value.clone().into()),
+            false => Value::Unprotected(dto.value.clone()),
+        });
+    }
+    let bytes = STANDARD
+        .decode(&dto.value)
+        .map_err(|e| format!("Invalid base64 field value: {e}"))?;
+    if dto.protected {
+        return Ok(Value::Protected(bytes.into()));
+    }
+    // KDBX stores every value as XML text, so bytes (only ever read from
+    // KDB files) must still be UTF-8 to be saved.
+    if std::str::from_utf8(&bytes).is_err() {
+        return Err("Binary field value is not UTF-8, which KDBX cannot store".to_string());
+    }
+    Ok(Value::Bytes(bytes))
+}
+
+fn look_to_dto(icon_id: Option<usize>, custom_icon: Option<Uuid>, data: &CustomData) -> LookDumpDto {
+    LookDumpDto {
+        icon_id,
+        custom_icon: custom_icon.map(|u| u.to_string()),
+        // An item without a value carries nothing worth keeping.
+        custom_data: data
+            .items
+            .iter()
+            .filter_map(|(key, item)| {
+                let value = value_to_dto(item.value.as_ref()?);
+                let modified = item
+                    .last_modification_time
+                    .map(|t| t.format(TIME_FORMAT).to_string());
+                Some((key.clone(), CustomDataDumpDto { value, modified }))
+            })
+            .collect(),
+    }
+}
+
+/// Icon id, custom icon and custom data from their dump form.
+fn look_from_dto(dto: &LookDumpDto) -> Result<(Option<usize>, Option<Uuid>, CustomData), String> {
+    let mut data = CustomData::default();
+    for (key, item) in &dto.custom_data {
+        let last_modification_time = item
+            .modified
+            .as_deref()
+            .map(|t| NaiveDateTime::parse_from_str(t, TIME_FORMAT))
+            .transpose()
+            .map_err(|e| format!("Invalid custom data timestamp of {key}: {e}"))?;
+        data.items.insert(
+            key.clone(),
+            CustomDataItem {
+                value: Some(value_from_dto(&item.value)?),
+                last_modification_time,
+            },
+        );
+    }
+    let custom_icon = dto.custom_icon.as_deref().map(parse_uuid).transpose()?;
+    Ok((dto.icon_id, custom_icon, data))
+}
+
+fn parse_color(color: Option<&String>) -> Result<Option<Color>, String> {
+    color
+        .map(|c| Color::from_str(c).map_err(|e| format!("Invalid colour '{c}': {e}")))
+        .transpose()
+}
+
+fn entry_to_dump(entry: &Entry) -> PostDumpDto {
+    let mut fields = BTreeMap::new();
+    for (key, value) in &entry.fields {
+        if STANDARD_POST_FIELDS.contains(&key.as_str()) {
+            continue;
+        }
+        fields.insert(key.clone(), value_to_dto(value));
+    }
+
+    PostDumpDto {
+        id: entry.uuid.to_string(),
+        title: entry.get_title().unwrap_or("").to_string(),
+        author: entry.get_username().unwrap_or("").to_string(),
+        body: entry.get("Notes").unwrap_or("").to_string(),
+        fields,
+        tags: entry.tags.clone(),
+        times: times_to_dto(&entry.times),
+        look: look_to_dto(entry.icon_id, entry.custom_icon_uuid, &entry.custom_data),
+        foreground_color: entry.foreground_color.as_ref().map(Color::to_string),
+        background_color: entry.background_color.as_ref().map(Color::to_string),
+        override_url: entry.override_url.clone(),
+        history: entry
+            .history
+            .as_ref()
+            .map(|h| h.get_entries().iter().map(entry_to_dump).collect())
+            .unwrap_or_default(),
+    }
+}
+
+fn group_to_dump(group: &Group) -> GroupDumpDto {
+    let mut groups = Vec::new();
+    let mut posts = Vec::new();
+    let mut order = Vec::new();
+    for node in &group.children {
+        match node.as_ref() {
+            NodeRef::Group(g) => {
+                order.push(g.uuid.to_string());
+                groups.push(group_to_dump(g));
+            }
+            NodeRef::Entry(e) => {
+                order.push(e.uuid.to_string());
+                posts.push(entry_to_dump(e));
+            }
+        }
+    }
+    // Groups followed by posts is what restoring does anyway.
+    let groups_first = group
+        .children
+        .iter()
+        .skip_while(|node| matches!(node.as_ref(), NodeRef::Group(_)))
+        .all(|node| matches!(node.as_ref(), NodeRef::Entry(_)));
+    if groups_first {
+        order.clear();
+    }
+
+    GroupDumpDto {
+        id: group.uuid.to_string(),
+        name: group.name.clone(),
+        notes: group.notes.clone(),
+        times: times_to_dto(&group.times),
+        look: look_to_dto(group.icon_id, group.custom_icon_uuid, &group.custom_data),
+        groups,
+        posts,
+        order,
+    }
+}
+
+/// Convert the whole database into its dump representation.
+pub fn database_to_dump(db: &Database) -> ForumDumpDto {
+    ForumDumpDto {
+        schema_version: DUMP_SCHEMA_VERSION,
+        database_name: db.meta.database_name.clone(),
+        custom_icons: db
+            .meta
+            .custom_icons
+            .icons
+            .iter()
+            .map(|icon| (icon.uuid.to_string(), STANDARD.encode(&icon.data)))
+            .collect(),
+        root: group_to_dump(&db.root),
+    }
+}
+
+fn parse_uuid(id: &str) -> Result<Uuid, String> {
+    Uuid::from_str(id).map_err(|e| format!("Invalid UUID '{id}': {e}"))
+}
+
+fn entry_from_dump(dto: &PostDumpDto) -> Result<Entry, String> {
+    let mut entry = Entry::new();
+    entry.uuid = parse_uuid(&dto.id)?;
+    entry.tags = dto.tags.clone();
+    entry.times = times_from_dto(&dto.times)?;
+
+    for (key, value) in [
+        ("Title", &dto.title),
+        ("UserName", &dto.author),
+        ("Notes", &dto.body),
+    ] {
+        entry
+            .fields
+            .insert(key.to_string(), Value::Unprotected(value.clone()));
+    }
+    for (key, field) in &dto.fields {
+        entry.fields.insert(key.clone(), value_from_dto(field)?);
+    }
+    (entry.icon_id, entry.custom_icon_uuid, entry.custom_data) = look_from_dto(&dto.look)?;
+    entry.foreground_color = parse_color(dto.foreground_color.as_ref())?;
+    entry.background_color = parse_color(dto.background_color.as_ref())?;
+    entry.override_url = dto.override_url.clone();
+
+    if !dto.history.is_empty() {
+        let mut history = History::default();
+        // add_entry prepends, so replay oldest first to keep newest-first order.
+        for old in dto.history.iter().rev() {
+            history.add_entry(entry_from_dump(old)?);
+        }
+        entry.history = Some(history);
+    }
+
+    Ok(entry)
+}
+
+fn group_from_dump(dto: &GroupDumpDto) -> Result<Group, String> {
+    let mut group = Group::new(&dto.name);
+    group.uuid = parse_uuid(&dto.id)?;
+    group.notes = dto.notes.clone();
+    group.times = times_from_dto(&dto.times)?;
+    (group.icon_id, group.custom_icon_uuid, group.custom_data) = look_from_dto(&dto.look)?;
+
+    for child in &dto.groups {
+        group.add_child(group_from_dump(child)?);
+    }
+    for post in &dto.posts {
+        group.add_child(entry_from_dump(post)?);
+    }
+    if !dto.order.is_empty() {
+        let position = |id: Uuid| {
+            let id = id.to_string();
+            dto.order.iter().position(|o| *o == id).unwrap_or(usize::MAX)
+        };
+        group.children.sort_by_key(|node| match node.as_ref() {
+            NodeRef::Group(g) => position(g.uuid),
+            NodeRef::Entry(e) => position(e.uuid),
+        });
+    }
+
+    Ok(group)
+}
+
+/// Rebuild a new KDBX4 database from a dump. Version 1 dumps, which had no
+/// child order, binary fields or icons, are still read.
+pub fn database_from_dump(dump: &ForumDumpDto) -> Result<Database, String> {
+    if !(1..=DUMP_SCHEMA_VERSION).contains(&dump.schema_version) {
+        return Err(format!(
+            "Unsupported dump schema version {} (expected at most {DUMP_SCHEMA_VERSION})",
+            dump.schema_version
+        ));
+    }
+
+    let mut db = Database::new(DatabaseConfig::default());
+    db.meta.database_name = dump.database_name.clone();
+    for (id, data) in &dump.custom_icons {
+        db.meta.custom_icons.icons.push(Icon {
+            uuid: parse_uuid(id)?,
+            data: STANDARD
+                .decode(data)
+                .map_err(|e| format!("Invalid custom icon {id}: {e}"))?,
+        });
+    }
+    db.root = group_from_dump(&dump.root)?;
+    Ok(db)
+}
+
+fn flatten_group(
+    group: &GroupDumpDto,
+    parent_id: Option<&str>,
+    out: &mut impl Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let record = DumpRecordDto::Group {
+        parent_id: parent_id.map(str::to_string),
+        group: GroupDumpDto {
+            id: group.id.clone(),
+            name: group.name.clone(),
+            notes: group.notes.clone(),
+            times: group.times.clone(),
+            look: group.look.clone(),
+            groups: Vec::new(),
+            posts: Vec::new(),
+            order: group.order.clone(),
+        },
+    };
+    writeln!(out, "{}", serde_json::to_string(&record)?)?;
+
+    for post in &group.posts {
+        let record = DumpRecordDto::Post {
+            parent_id: group.id.clone(),
+            post: post.clone(),
+        };
+        writeln!(out, "{}", serde_json::to_string(&record)?)?;
+    }
+    for child in &group.groups {
+        flatten_group(child, Some(&group.id), out)?;
+    }
+    Ok(())
+}
+
+/// Write a dump as NDJSON: a header line, then one line per group and post.
+pub fn write_ndjson(
+    dump: &ForumDumpDto,
+    out: &mut impl Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let header = DumpRecordDto::Header {
+        schema_version: dump.schema_version,
+        database_name: dump.database_name.clone(),
+        custom_icons: dump.custom_icons.clone(),
+    };
+    writeln!(out, "{}", serde_json::to_string(&header)?)?;
+    flatten_group(&dump.root, None, out)
+}
+
+/// Read an NDJSON dump back into the nested representation.
+pub fn read_ndjson(input: impl BufRead) -> Result<ForumDumpDto, String> {
+    let mut header = None;
+    let mut groups: Vec<(Option<String>, GroupDumpDto)> = Vec::new();
+    let mut posts: HashMap<String, Vec<PostDumpDto>> = HashMap::new();
+
+    for (n, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: DumpRecordDto =
+            serde_json::from_str(&line).map_err(|e| format!("Line {}: {e}", n + 1))?;
+        match record {
+            DumpRecordDto::Header {
+                schema_version,
+                database_name,
+                custom_icons,
+            } => header = Some((schema_version, database_name, custom_icons)),
+            DumpRecordDto::Group { parent_id, group } => groups.push((parent_id, group)),
+            DumpRecordDto::Post { parent_id, post } => {
+                posts.entry(parent_id).or_default().push(post)
+            }
+        }
+    }
+
+    let (schema_version, database_name, custom_icons) =
+        header.ok_or_else(|| "Missing NDJSON header record".to_string())?;
+
+    // Groups are written parent-first, so attach them children-first.
+    let mut children: HashMap<String, Vec<GroupDumpDto>> = HashMap::new();
+    let mut root = None;
+    for (parent_id, mut group) in groups.into_iter().rev() {
+        group.posts = posts.remove(&group.id).unwrap_or_default();
+        let mut kids = children.remove(&group.id).unwrap_or_default();
+        kids.reverse();
+        group.groups = kids;
+        match parent_id {
+            Some(parent_id) => children.entry(parent_id).or_default().push(group),
+            None => root = Some(group),
+        }
+    }
+
+    if let Some(orphan) = children.keys().chain(posts.keys()).next() {
+        return Err(format!("Record refers to unknown parent {orphan}"));
+    }
+
+    Ok(ForumDumpDto {
+        schema_version,
+        database_name,
+        custom_icons,
+        root: root.ok_or_else(|| "Missing root group record".to_string())?,
+    })
+}
+
+/// Parse a dump in either format, detected from its first line.
+pub fn parse_dump(text: &str) -> Result<ForumDumpDto, String> {
+    let first_line = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    if serde_json::from_str::<DumpRecordDto>(first_line).is_ok() {
+        read_ndjson(text.as_bytes())
+    } else {
+        serde_json::from_str(text).map_err(|e| format!("Invalid JSON dump: {e}"))
+    }
+}
@@ -0,0 +1,81 @@
+use serde::Serialize;
+
+/// Capacity of the live update channel; slow subscribers skip missed events.
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// A change to the forum, pushed to subscribers of `GET /events`.
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ForumEvent {
+    ThreadCreated {
+        category_id: String,
+        thread_id: String,
+        post_id: String,
+        title: String,
+        post_count: usize,
+    },
+    ReplyCreated {
+        category_id: String,
+        thread_id: String,
+        post_id: String,
+        post_count: usize,
+    },
+    /// A moderator deleted a thread or hid its opening post.
+    ThreadRemoved {
+        category_id: String,
+        thread_id: String,
+        post_id: String,
+    },
+    /// A moderator deleted or hid a reply; `post_count` is what remains.
+    PostRemoved {
+        category_id: String,
+        thread_id: String,
+        post_id: String,
+        post_count: usize,
+    },
+}
+
+impl ForumEvent {
+    /// SSE event name, so clients can listen per event type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForumEvent::ThreadCreated { .. } => "thread_created",
+            ForumEvent::ReplyCreated { .. } => "reply_created",
+            ForumEvent::ThreadRemoved { .. } => "thread_removed",
+            ForumEvent::PostRemoved { .. } => "post_removed",
+        }
+    }
+
+    pub fn category_id(&self) -> &str {
+        match self {
+            ForumEvent::ThreadCreated { category_id, .. }
+            | ForumEvent::ReplyCreated { category_id, .. }
+            | ForumEvent::ThreadRemoved { category_id, .. }
+            | ForumEvent::PostRemoved { category_id, .. } => category_id,
+        }
+    }
+
+    pub fn thread_id(&self) -> &str {
+        match self {
+            ForumEvent::ThreadCreated { thread_id, .. }
+            | ForumEvent::ReplyCreated { thread_id, .. }
+            | ForumEvent::ThreadRemoved { thread_id, .. }
+            | ForumEvent::PostRemoved { thread_id, .. } => thread_id,
+        }
+    }
+
+    pub fn post_id(&self) -> &str {
+        match self {
+            ForumEvent::ThreadCreated { post_id, .. }
+            | ForumEvent::ReplyCreated { post_id, .. }
+            | ForumEvent::ThreadRemoved { post_id, .. }
+            | ForumEvent::PostRemoved { post_id, .. } => post_id,
+        }
+    }
+
+    /// Whether the event matches optional category/thread subscription filters.
+    pub fn matches(&self, category_id: Option<&str>, thread_id: Option<&str>) -> bool {
+        category_id.is_none_or(|c| c == self.category_id())
+            && thread_id.is_none_or(|t| t == self.thread_id())
+    }
+}
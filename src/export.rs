@@ -0,0 +1,95 @@
+use keepass::{
+    config::DatabaseConfig,
+    db::{Group, Node},
+    Database, DatabaseKey,
+};
+
+use crate::db::{categories, find_group_by_id, find_thread_by_id};
+
+/// Find the top-level category that contains the group with the given id.
+pub fn find_category_of<'a>(root: &'a Group, group_id: &str) -> Option<&'a Group> {
+    categories(root).find(|category| find_group_by_id(category, group_id).is_some())
+}
+
+/// Find or create a category group with the given name directly under `root`.
+fn category_in<'a>(root: &'a mut Group, source: &Group) -> &'a mut Group {
+    let existing = root
+        .children
+        .iter()
+        .position(|node| matches!(node, Node::Group(g) if g.uuid == source.uuid));
+
+    let idx = match existing {
+        Some(idx) => idx,
+        None => {
+            let mut category = source.clone();
+            category.children.clear();
+            root.add_child(category);
+            root.children.len() - 1
+        }
+    };
+
+    match &mut root.children[idx] {
+        Node::Group(g) => g,
+        Node::Entry(_) => unreachable!("category index always points at a group"),
+    }
+}
+
+/// Build a standalone forum database holding copies of the selected
+/// categories and threads.
+///
+/// Whole categories are copied as-is. Threads are copied under a category
+/// group with the same UUID and name as their source category, so the result
+/// keeps the root → category → thread layout and can be served on its own.
+/// The system group is not a category, so it is never exported.
+pub fn build_export_database(
+    source: &Database,
+    category_ids: &[String],
+    thread_ids: &[String],
+) -> Result<Database, String> {
+    if category_ids.is_empty() && thread_ids.is_empty() {
+        return Err("Nothing selected for export".to_string());
+    }
+
+    let mut out = Database::new(DatabaseConfig::default());
+    out.root.name = source.root.name.clone();
+    out.meta.database_name = Some(format!(
+        "{} (export)",
+        source.meta.database_name.as_deref().unwrap_or("kdbx-forum")
+    ));
+    out.meta.memory_protection = source.meta.memory_protection.clone();
+
+    for category_id in category_ids {
+        let category = categories(&source.root)
+            .find(|g| g.uuid.to_string() == *category_id)
+            .ok_or_else(|| format!("Category {category_id} not found"))?;
+
+        out.root
+            .children
+            .retain(|node| !matches!(node, Node::Group(g) if g.uuid == category.uuid));
+        out.root.add_child(category.clone());
+    }
+
+    for thread_id in thread_ids {
+        let (Some(category), Some(thread)) = (
+            find_category_of(&source.root, thread_id),
+            find_thread_by_id(&source.root, thread_id),
+        ) else {
+            return Err(format!("Thread {thread_id} not found"));
+        };
+
+        let target = category_in(&mut out.root, category);
+        if find_group_by_id(target, thread_id).is_none() {
+            target.add_child(thread.clone());
+        }
+    }
+
+    Ok(out)
+}
+
+/// Serialize a database with the given key into an in-memory KDBX4 file.
+pub fn database_to_bytes(db: &Database, key: &DatabaseKey) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    db.save(&mut buf, key.clone())
+        .map_err(|e| format!("Failed to write export: {e}"))?;
+    Ok(buf)
+}
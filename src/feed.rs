@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use keepass::db::{Entry, Group, NodeRef, Times};
+
+use crate::{moderation::is_published, site::escape_html};
+
+/// Default number of entries in a feed.
+pub const DEFAULT_FEED_LIMIT: usize = 50;
+
+/// A post as it appears in a feed.
+pub struct FeedItem {
+    pub id: String,
+    pub thread_id: String,
+    pub thread_title: String,
+    pub title: String,
+    pub author: String,
+    pub body: String,
+    pub published: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+/// Map of feed token to the user it belongs to.
+#[derive(Clone, Default)]
+pub struct FeedTokens(HashMap<String, String>);
+
+impl FeedTokens {
+    /// Parse `user=token` pairs as given on the command line.
+    pub fn parse(pairs: &[String]) -> Result<Self, String> {
+        let mut tokens = HashMap::new();
+        for pair in pairs {
+            let (user, token) = pair
+                .split_once('=')
+                .filter(|(u, t)| !u.is_empty() && !t.is_empty())
+                .ok_or_else(|| format!("Invalid feed token '{pair}', expected USER=TOKEN"))?;
+            tokens.insert(token.to_string(), user.to_string());
+        }
+        Ok(Self(tokens))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Return the user owning `token`, comparing against every token in constant time.
+    pub fn user_for(&self, token: &str) -> Option<&str> {
+        let mut found = None;
+        for (candidate, user) in &self.0 {
+            if constant_time_eq(candidate.as_bytes(), token.as_bytes()) {
+                found = Some(user.as_str());
+            }
+        }
+        found
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn entry_time(times: &Times, last_modified: bool) -> NaiveDateTime {
+    let time = if last_modified {
+        times.get_last_modification()
+    } else {
+        times.get_creation()
+    };
+    time.copied().unwrap_or_else(Times::epoch)
+}
+
+fn entry_to_feed_item(entry: &Entry, thread: &Group) -> FeedItem {
+    FeedItem {
+        id: entry.uuid.to_string(),
+        thread_id: thread.uuid.to_string(),
+        thread_title: thread.name.clone(),
+        title: entry.get_title().unwrap_or("").to_string(),
+        author: entry.get_username().unwrap_or("").to_string(),
+        body: entry.get("Notes").unwrap_or("").to_string(),
+        published: entry_time(&entry.times, false),
+        updated: entry_time(&entry.times, true),
+    }
+}
+
+/// Collect the published posts of a thread.
+fn collect_thread_items(thread: &Group, out: &mut Vec<FeedItem>) {
+    for node in &thread.children {
+        if let NodeRef::Entry(e) = node.as_ref()
+            && is_published(e)
+        {
+            out.push(entry_to_feed_item(e, thread));
+        }
+    }
+}
+
+/// Collect all published posts of the threads (child groups) in a category.
+///
+/// Like the thread listing, threads whose opening post is held or hidden are
+/// left out entirely.
+pub fn collect_category_items(category: &Group, out: &mut Vec<FeedItem>) {
+    for node in &category.children {
+        if let NodeRef::Group(thread) = node.as_ref()
+            && thread.entries().first().is_none_or(|e| is_published(e))
+        {
+            collect_thread_items(thread, out);
+        }
+    }
+}
+
+/// Keep only the `limit` most recently updated items, newest first.
+pub fn most_recent(mut items: Vec<FeedItem>, limit: usize) -> Vec<FeedItem> {
+    items.sort_by_key(|item| std::cmp::Reverse(item.updated));
+    items.truncate(limit);
+    items
+}
+
+fn rfc3339(time: &NaiveDateTime) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Render the body as HTML: escaped text with line breaks kept.
+fn render_body(body: &str) -> String {
+    escape_html(body).replace('\n', "<br>\n")
+}
+
+/// Render an Atom 1.0 feed.
+///
+/// `feed_id` is a stable identifier for the feed, `base_url` the absolute URL
+/// of the forum and `self_path` the feed's own path under it.
+pub fn render_atom(
+    feed_id: &str,
+    title: &str,
+    base_url: &str,
+    self_path: &str,
+    items: &[FeedItem],
+) -> String {
+    let updated = items
+        .iter()
+        .map(|i| i.updated)
+        .max()
+        .unwrap_or_else(Times::epoch);
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n  \
+         <id>{}</id>\n  <title>{}</title>\n  <updated>{}</updated>\n  \
+         <link rel=\"self\" href=\"{}{}\"/>\n  <link rel=\"alternate\" href=\"{}/\"/>\n",
+        escape_html(feed_id),
+        escape_html(title),
+        rfc3339(&updated),
+        escape_html(base_url),
+        escape_html(self_path),
+        escape_html(base_url),
+    );
+
+    for item in items {
+        let title = if item.title.is_empty() {
+            &item.thread_title
+        } else {
+            &item.title
+        };
+        let author = if item.author.is_empty() {
+            "Anonymous"
+        } else {
+            &item.author
+        };
+        xml.push_str(&format!(
+            "  <entry>\n    <id>urn:uuid:{}</id>\n    <title>{}</title>\n    \
+             <link href=\"{}/#thread={}\"/>\n    <author><name>{}</name></author>\n    \
+             <published>{}</published>\n    <updated>{}</updated>\n    \
+             <content type=\"html\">{}</content>\n  </entry>\n",
+            escape_html(&item.id),
+            escape_html(title),
+            escape_html(base_url),
+            escape_html(&item.thread_id),
+            escape_html(author),
+            rfc3339(&item.published),
+            rfc3339(&item.updated),
+            escape_html(&render_body(&item.body)),
+        ));
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
@@ -0,0 +1,554 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Path, Request, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{any, get, post},
+    Json, Router,
+};
+use keepass::error::{DatabaseKeyError, DatabaseOpenError};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use tower::ServiceExt;
+
+use crate::{
+    app::ForumApp,
+    audit::{record_event, Actor},
+    config::ServeConfig,
+    db::{build_db_key, open_database},
+    dto::ForumSummaryDto,
+    lockfile::LockFile,
+    routes::{healthz, request_user},
+};
+
+/// One forum of a server hosting several, from a `[[forums]]` table of the
+/// config file or a `--forum NAME=PATH` flag.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ForumConfig {
+    /// Served under `/f/<name>`.
+    pub name: String,
+    pub database: PathBuf,
+    pub keyfile: Option<PathBuf>,
+    /// Unlocks the forum at startup; without it the forum stays locked
+    /// until someone enters the password on the landing page.
+    pub password: Option<String>,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl ForumConfig {
+    /// Parse `NAME=PATH` as given to `--forum`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, path) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid forum '{spec}', expected NAME=PATH"))?;
+        Ok(Self {
+            name: name.trim().to_string(),
+            database: PathBuf::from(path.trim()),
+            keyfile: None,
+            password: None,
+            read_only: false,
+        })
+    }
+
+    /// Where the forum is mounted.
+    pub fn path(&self) -> String {
+        format!("/f/{}", self.name)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let valid = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!(
+                "Invalid forum name '{}': use lowercase letters, digits, '-' and '_'",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An unlocked forum, with the lock file that keeps other writers away.
+struct OpenForum {
+    app: ForumApp,
+    _lock: Option<LockFile>,
+}
+
+/// Longest wait between two unlock attempts after wrong passwords.
+const MAX_UNLOCK_DELAY: Duration = Duration::from_secs(300);
+
+/// Wrong passwords given for one forum. Every one doubles the wait before
+/// the next attempt, so the password cannot be guessed at the pace of the
+/// key derivation.
+#[derive(Default)]
+struct UnlockBackoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl UnlockBackoff {
+    fn check(&self) -> Result<(), Failure> {
+        let now = Instant::now();
+        match self.retry_at {
+            Some(at) if at > now => Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "Too many wrong passwords, try again in {} s",
+                    (at - now).as_secs() + 1
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure(&mut self) {
+        self.failures += 1;
+        let delay = Duration::from_secs(1 << self.failures.saturating_sub(1).min(16));
+        self.retry_at = Some(Instant::now() + delay.min(MAX_UNLOCK_DELAY));
+    }
+}
+
+struct Forum {
+    config: ForumConfig,
+    /// `None` while locked: nothing decrypted is kept in memory.
+    open: RwLock<Option<OpenForum>>,
+    backoff: Mutex<UnlockBackoff>,
+}
+
+struct Inner {
+    forums: BTreeMap<String, Forum>,
+    config: ServeConfig,
+}
+
+/// Every forum of a server hosting several, each unlocked and locked on
+/// its own while the process keeps running.
+#[derive(Clone)]
+pub struct Forums {
+    inner: Arc<Inner>,
+}
+
+type Failure = (StatusCode, String);
+
+impl Forums {
+    pub fn new(config: ServeConfig) -> Result<Self, String> {
+        let mut forums = BTreeMap::new();
+        for forum in &config.forums {
+            forum.validate()?;
+            let read_only = forum.read_only || config.read_only;
+            let forum = Forum {
+                config: ForumConfig {
+                    read_only,
+                    ..forum.clone()
+                },
+                open: RwLock::new(None),
+                backoff: Mutex::default(),
+            };
+            if forums.insert(forum.config.name.clone(), forum).is_some() {
+                return Err("Forum names must be unique".to_string());
+            }
+        }
+        Ok(Self {
+            inner: Arc::new(Inner { forums, config }),
+        })
+    }
+
+    pub fn config(&self) -> &ServeConfig {
+        &self.inner.config
+    }
+
+    fn forum(&self, name: &str) -> Result<&Forum, Failure> {
+        self.inner
+            .forums
+            .get(name)
+            .ok_or((StatusCode::NOT_FOUND, "Forum not found".to_string()))
+    }
+
+    /// Unlock every forum whose password is in the configuration.
+    pub async fn unlock_configured(&self) -> Result<(), Box<dyn Error>> {
+        for forum in self.inner.forums.values() {
+            let Some(password) = forum.config.password.clone() else {
+                tracing::info!(forum = %forum.config.name, "Forum stays locked until unlocked");
+                continue;
+            };
+            self.unlock(&forum.config.name, password, Actor::local("server"))
+                .await
+                .map_err(|(_, e)| format!("Failed to unlock forum {}: {e}", forum.config.name))?;
+        }
+        Ok(())
+    }
+
+    /// Decrypt a forum and start serving it. After a wrong password the
+    /// next attempt has to wait, see `UnlockBackoff`.
+    pub async fn unlock(&self, name: &str, password: String, actor: Actor) -> Result<(), Failure> {
+        let poisoned = || {
+            (StatusCode::INTERNAL_SERVER_ERROR, "Unlock state is poisoned".to_string())
+        };
+        let forum = self.forum(name)?;
+        // Held while the key is derived, so requests wait for the forum
+        // instead of being told it is locked, and attempts take turns.
+        let mut open = forum.open.write().await;
+        if open.is_some() {
+            return Err((StatusCode::CONFLICT, "Forum is already unlocked".to_string()));
+        }
+        forum.backoff.lock().map_err(|_| poisoned())?.check()?;
+        let forums = self.clone();
+        let name = name.to_string();
+        let result = tokio::task::spawn_blocking(move || forums.open(&name, password, &actor))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let mut backoff = forum.backoff.lock().map_err(|_| poisoned())?;
+        let opened = match result {
+            Ok(opened) => {
+                *backoff = UnlockBackoff::default();
+                opened
+            }
+            Err(failure) => {
+                if failure.0 == StatusCode::UNAUTHORIZED {
+                    backoff.record_failure();
+                    tracing::warn!(
+                        forum = %forum.config.name,
+                        failures = backoff.failures,
+                        "Wrong password for forum"
+                    );
+                }
+                return Err(failure);
+            }
+        };
+        drop(backoff);
+        tracing::info!(forum = %forum.config.name, "Unlocked forum");
+        *open = Some(opened);
+        Ok(())
+    }
+
+    /// The blocking part of `unlock`: key derivation and the first save.
+    fn open(&self, name: &str, password: String, actor: &Actor) -> Result<OpenForum, Failure> {
+        let internal = |e: Box<dyn Error>| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        let config = &self.forum(name)?.config;
+        let key = build_db_key(Some(password), &config.keyfile).map_err(internal)?;
+        let lock = match config.read_only {
+            true => None,
+            false => Some(
+                LockFile::acquire(&config.database, "serve")
+                    .map_err(|e| (StatusCode::CONFLICT, e))?,
+            ),
+        };
+        let db = open_database(&config.database, &key).map_err(|e| {
+            match e.downcast_ref::<DatabaseOpenError>() {
+                Some(DatabaseOpenError::Key(DatabaseKeyError::IncorrectKey)) => {
+                    (StatusCode::UNAUTHORIZED, "Wrong password or key file".to_string())
+                }
+                _ => internal(e),
+            }
+        })?;
+        let detail = format!("{}{}", self.inner.config.listen, config.path());
+        let app = ForumApp::start(
+            db,
+            config.database.clone(),
+            key,
+            &self.inner.config,
+            config.read_only,
+            &config.path(),
+            actor,
+            &detail,
+        )
+        .map_err(internal)?;
+        Ok(OpenForum { app, _lock: lock })
+    }
+
+    /// Stop serving a forum and forget its decrypted content.
+    pub async fn lock(&self, name: &str, actor: &Actor) -> Result<(), Failure> {
+        let forum = self.forum(name)?;
+        // Held until the forum has stopped, so no request reaches it half way.
+        let mut guard = forum.open.write().await;
+        let Some(open) = guard.take() else {
+            return Err((StatusCode::CONFLICT, "Forum is already locked".to_string()));
+        };
+        let state = &open.app.state;
+        if !state.read_only {
+            let mut db = state.db.write().await;
+            let target = db.root.uuid.to_string();
+            record_event(&mut db, actor, "lock", &target, None);
+            if let Err(e) = state.save(&db) {
+                tracing::warn!(forum = %name, error = %e, "Failed to record the lock");
+            }
+        }
+        open.app.stop().await;
+        drop(guard);
+        tracing::info!(forum = %name, "Locked forum");
+        Ok(())
+    }
+
+    /// Lock every forum on the way out, letting saves under way finish.
+    pub async fn lock_all(&self) {
+        for forum in self.inner.forums.values() {
+            let mut guard = forum.open.write().await;
+            if let Some(open) = guard.take() {
+                open.app.stop().await;
+            }
+        }
+    }
+
+    async fn summaries(&self) -> Vec<ForumSummaryDto> {
+        let mut out = Vec::new();
+        for forum in self.inner.forums.values() {
+            let open = forum.open.read().await;
+            let title = match open.as_ref() {
+                Some(open) => Some(open.app.state.db.read().await.root.name.clone()),
+                None => None,
+            };
+            out.push(ForumSummaryDto {
+                name: forum.config.name.clone(),
+                path: forum.config.path(),
+                unlocked: open.is_some(),
+                read_only: forum.config.read_only,
+                title,
+            });
+        }
+        out
+    }
+
+    /// The landing page, the unlock and lock endpoints, and every forum
+    /// under `/f/<name>/`.
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/", get(landing))
+            .route("/forums", get(list_forums))
+            .route("/forums/:name/unlock", post(unlock_forum))
+            .route("/forums/:name/lock", post(lock_forum))
+            .route("/healthz", get(healthz))
+            .route("/f/:forum", get(add_slash))
+            .route("/f/:forum/", any(forward))
+            .route("/f/:forum/*rest", any(forward))
+            .with_state(self.clone())
+    }
+}
+
+fn failure_response((status, message): Failure) -> Response {
+    (status, message).into_response()
+}
+
+/// `GET /forums`: every forum and whether it is unlocked.
+async fn list_forums(State(forums): State<Forums>) -> Json<Vec<ForumSummaryDto>> {
+    Json(forums.summaries().await)
+}
+
+#[derive(Deserialize)]
+pub struct UnlockRequest {
+    pub password: String,
+}
+
+/// `POST /forums/:name/unlock`: the password is the only credential, so
+/// anyone who knows it may unlock. Wrong passwords are answered with 429
+/// for a while, longer after each one.
+async fn unlock_forum(
+    State(forums): State<Forums>,
+    Path(name): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<UnlockRequest>,
+) -> Response {
+    let user = request_user(&headers).unwrap_or_default();
+    let actor = Actor::new(&user, Some(addr.ip().to_string()));
+    match forums.unlock(&name, payload.password, actor).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(failure) => failure_response(failure),
+    }
+}
+
+/// `POST /forums/:name/lock`, for moderators only. Like moderation, this
+/// trusts the user header.
+async fn lock_forum(
+    State(forums): State<Forums>,
+    Path(name): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    if !forums.config().moderators.contains(&user) {
+        return (StatusCode::FORBIDDEN, "Moderators only").into_response();
+    }
+    let actor = Actor::new(&user, Some(addr.ip().to_string()));
+    match forums.lock(&name, &actor).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(failure) => failure_response(failure),
+    }
+}
+
+/// `/f/<name>` to `/f/<name>/`, so the forum's page finds its API.
+async fn add_slash(Path(name): Path<String>) -> Redirect {
+    Redirect::permanent(&format!("/f/{name}/"))
+}
+
+/// Hand a request under `/f/<name>/` to that forum's router, with the
+/// prefix taken off the path.
+async fn forward(State(forums): State<Forums>, request: Request) -> Response {
+    let path = request.uri().path().strip_prefix("/f/").unwrap_or_default();
+    let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+    let forum = match forums.forum(name) {
+        Ok(forum) => forum,
+        Err(failure) => return failure_response(failure),
+    };
+    let router = match forum.open.read().await.as_ref() {
+        Some(open) => open.app.router.clone(),
+        None => return (StatusCode::SERVICE_UNAVAILABLE, "Forum is locked").into_response(),
+    };
+    let target = match request.uri().query() {
+        Some(query) => format!("/{rest}?{query}"),
+        None => format!("/{rest}"),
+    };
+    let Ok(uri) = target.parse::<Uri>() else {
+        return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
+    };
+
+    // A fresh request rather than the original with a new URI: the path
+    // parameters of the outer route would otherwise reach the forum's own
+    // `Path` extractors.
+    let (parts, body) = request.into_parts();
+    let mut inner = Request::new(body);
+    *inner.method_mut() = parts.method;
+    *inner.uri_mut() = uri;
+    *inner.version_mut() = parts.version;
+    *inner.headers_mut() = parts.headers;
+    if let Some(info) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        inner.extensions_mut().insert(*info);
+    }
+    match router.oneshot(inner).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+/// Landing page: every forum with a way to unlock or lock it.
+async fn landing() -> Html<&'static str> {
+    Html(
+        r#"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>kdbx-forum</title>
+  <style>
+    body { font-family: system-ui, sans-serif; max-width: 720px; margin: 2rem auto; }
+    ul { list-style: none; padding-left: 0; }
+    li { margin: 0.75rem 0; padding-bottom: 0.75rem; border-bottom: 1px solid #eee; }
+    a { color: #0366d6; text-decoration: none; }
+    a:hover { text-decoration: underline; }
+    .muted { color: #666; font-size: 0.9rem; }
+    .error { color: #d73a49; font-size: 0.9rem; }
+  </style>
+</head>
+<body>
+  <h2>kdbx-forum</h2>
+  <p class="muted">Each forum is a KeePass KDBX file of its own. A locked forum keeps
+    nothing decrypted in memory until someone enters its password.</p>
+
+  <h3>Your name</h3>
+  <input type="text" id="username" placeholder="Anonymous" />
+  <p class="muted">Only moderators may lock a forum again.</p>
+
+  <h3>Forums</h3>
+  <ul id="forums"></ul>
+
+  <script>
+    const username = document.getElementById('username');
+    username.value = localStorage.getItem('kdbx_forum_username') || '';
+    username.addEventListener('input', () => {
+      localStorage.setItem('kdbx_forum_username', username.value);
+    });
+
+    function userHeaders() {
+      const name = username.value.trim();
+      return name ? { 'X-Forum-User': encodeURIComponent(name) } : {};
+    }
+
+    async function unlock(name, password, status) {
+      status.textContent = 'Unlocking…';
+      const res = await fetch('/forums/' + encodeURIComponent(name) + '/unlock', {
+        method: 'POST',
+        headers: { ...userHeaders(), 'Content-Type': 'application/json' },
+        body: JSON.stringify({ password })
+      });
+      if (!res.ok) {
+        status.textContent = await res.text();
+        return;
+      }
+      await loadForums();
+    }
+
+    async function lock(name, status) {
+      const res = await fetch('/forums/' + encodeURIComponent(name) + '/lock', {
+        method: 'POST',
+        headers: userHeaders()
+      });
+      if (!res.ok) {
+        status.textContent = await res.text();
+        return;
+      }
+      await loadForums();
+    }
+
+    async function loadForums() {
+      const res = await fetch('/forums');
+      const forums = await res.json();
+      const list = document.getElementById('forums');
+      list.innerHTML = '';
+      for (const forum of forums) {
+        const li = document.createElement('li');
+        const title = document.createElement(forum.unlocked ? 'a' : 'strong');
+        title.textContent = forum.title || forum.name;
+        if (forum.unlocked) title.href = forum.path + '/';
+        li.appendChild(title);
+
+        const info = document.createElement('div');
+        info.className = 'muted';
+        info.textContent = forum.path + ' · ' + (forum.unlocked ? 'unlocked' : '🔒 locked') +
+          (forum.read_only ? ' · read-only' : '');
+        li.appendChild(info);
+
+        const status = document.createElement('div');
+        status.className = 'error';
+        if (forum.unlocked) {
+          const button = document.createElement('button');
+          button.textContent = 'Lock';
+          button.onclick = () => lock(forum.name, status).catch(console.error);
+          li.appendChild(button);
+        } else {
+          const form = document.createElement('form');
+          const password = document.createElement('input');
+          password.type = 'password';
+          password.placeholder = 'Password';
+          const button = document.createElement('button');
+          button.textContent = 'Unlock';
+          form.append(password, ' ', button);
+          form.onsubmit = (e) => {
+            e.preventDefault();
+            unlock(forum.name, password.value, status).catch(console.error);
+          };
+          li.appendChild(form);
+        }
+        li.appendChild(status);
+        list.appendChild(li);
+      }
+    }
+
+    loadForums().catch(console.error);
+  </script>
+</body>
+</html>
+"#,
+    )
+}
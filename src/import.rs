@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+
+use keepass::{
+    db::{CustomData, CustomDataItem, Entry, Group, Node, NodeRef, Value},
+    Database,
+};
+use uuid::Uuid;
+
+use crate::db::{find_group_by_id, is_system_group};
+
+/// Custom data key on a remapped copy holding the UUID it has in the file it
+/// came from, so importing that file again updates the copy instead of
+/// adding another one.
+const IMPORTED_FROM: &str = "kdbx-forum.imported-from";
+
+/// Whether a node with `uuid` and `custom_data` is the local copy of `incoming`.
+fn is_copy_of(uuid: Uuid, custom_data: &CustomData, incoming: Uuid) -> bool {
+    uuid == incoming
+        || matches!(
+            custom_data.items.get(IMPORTED_FROM).and_then(|item| item.value.as_ref()),
+            Some(Value::Unprotected(source)) if *source == incoming.to_string()
+        )
+}
+
+fn mark_imported_from(custom_data: &mut CustomData, source: Uuid) {
+    custom_data.items.insert(
+        IMPORTED_FROM.to_string(),
+        CustomDataItem {
+            value: Some(Value::Unprotected(source.to_string())),
+            last_modification_time: None,
+        },
+    );
+}
+
+/// What an import did (or would do, in dry-run mode).
+#[derive(Default)]
+pub struct ImportReport {
+    pub added_groups: usize,
+    pub added_entries: usize,
+    pub updated_groups: usize,
+    pub updated_entries: usize,
+    pub kept_existing: usize,
+    pub remapped: usize,
+    /// Groups nested inside a thread, whose posts went into the thread.
+    pub flattened_groups: usize,
+    pub log: Vec<String>,
+}
+
+impl ImportReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} groups added, {} groups updated, {} posts added, {} posts updated, \
+             {} unchanged (local copy up to date), {} UUIDs remapped, \
+             {} nested groups flattened",
+            self.added_groups,
+            self.updated_groups,
+            self.added_entries,
+            self.updated_entries,
+            self.kept_existing,
+            self.remapped,
+            self.flattened_groups
+        )
+    }
+}
+
+/// Collect every group and entry UUID under `group`, including itself.
+fn collect_uuids(group: &Group, out: &mut HashSet<Uuid>) {
+    out.insert(group.uuid);
+    for node in &group.children {
+        match node.as_ref() {
+            NodeRef::Entry(e) => {
+                out.insert(e.uuid);
+            }
+            NodeRef::Group(g) => collect_uuids(g, out),
+        }
+    }
+}
+
+/// True if `incoming` was modified after `existing`. Missing timestamps count as oldest.
+fn is_newer(incoming: &keepass::db::Times, existing: &keepass::db::Times) -> bool {
+    match (incoming.get_last_modification(), existing.get_last_modification()) {
+        (Some(i), Some(e)) => i > e,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+struct Importer<'a> {
+    /// UUIDs already present in the target database (plus those added so far).
+    known: HashSet<Uuid>,
+    report: &'a mut ImportReport,
+}
+
+impl Importer<'_> {
+    /// Merge `incoming` as a thread of the category `parent`.
+    ///
+    /// A group with the same UUID directly under `parent`, or remapped from
+    /// it by an earlier import, is treated as the same group and updated if
+    /// the incoming copy is newer. A UUID that exists anywhere else in the
+    /// target database is a collision and gets a fresh UUID. Threads hold
+    /// no groups, so the posts of groups nested in `incoming` are merged
+    /// into the thread itself.
+    fn merge_group(&mut self, parent: &mut Group, incoming: &Group) {
+        let existing = parent.children.iter().position(|node| {
+            matches!(node, Node::Group(g) if is_copy_of(g.uuid, &g.custom_data, incoming.uuid))
+        });
+
+        let idx = match existing {
+            Some(idx) => {
+                if let Node::Group(g) = &mut parent.children[idx]
+                    && is_newer(&incoming.times, &g.times)
+                {
+                    g.name = incoming.name.clone();
+                    g.notes = incoming.notes.clone();
+                    g.times = incoming.times.clone();
+                    self.report.updated_groups += 1;
+                    self.report
+                        .log
+                        .push(format!("update group '{}' ({})", g.name, g.uuid));
+                }
+                idx
+            }
+            None => {
+                let mut group = incoming.clone();
+                group.children.clear();
+                if self.known.contains(&group.uuid) {
+                    group.uuid = Uuid::new_v4();
+                    mark_imported_from(&mut group.custom_data, incoming.uuid);
+                    self.report.remapped += 1;
+                    self.report.log.push(format!(
+                        "remap group '{}' {} -> {}",
+                        group.name, incoming.uuid, group.uuid
+                    ));
+                }
+                self.known.insert(group.uuid);
+                self.report.added_groups += 1;
+                self.report
+                    .log
+                    .push(format!("add group '{}' ({})", group.name, group.uuid));
+                parent.add_child(group);
+                parent.children.len() - 1
+            }
+        };
+
+        let Node::Group(target) = &mut parent.children[idx] else {
+            unreachable!("merge target is always a group");
+        };
+        for node in &incoming.children {
+            match node.as_ref() {
+                NodeRef::Group(g) => self.flatten_into(target, g),
+                NodeRef::Entry(e) => self.merge_entry(target, e),
+            }
+        }
+    }
+
+    /// Merge the posts of `nested`, and of every group below it, into `thread`.
+    fn flatten_into(&mut self, thread: &mut Group, nested: &Group) {
+        self.report.flattened_groups += 1;
+        self.report.log.push(format!(
+            "flatten group '{}' ({}) into thread '{}'",
+            nested.name, nested.uuid, thread.name
+        ));
+        for node in &nested.children {
+            match node.as_ref() {
+                NodeRef::Group(g) => self.flatten_into(thread, g),
+                NodeRef::Entry(e) => self.merge_entry(thread, e),
+            }
+        }
+    }
+
+    /// Merge the children of `source` into the category `category`.
+    ///
+    /// Child groups become threads. Entries sitting directly in `source` are
+    /// gathered into a thread named after it, since posts cannot live directly
+    /// in a category. A child group with the category's own UUID (e.g. from a
+    /// file exported from this forum) is merged into the category itself.
+    /// The source's system group is never imported.
+    fn merge_contents(&mut self, category: &mut Group, source: &Group) {
+        let mut loose = source.clone();
+        loose.children.retain(|node| matches!(node, Node::Entry(_)));
+        if !loose.children.is_empty() {
+            self.merge_group(category, &loose);
+        }
+
+        for node in &source.children {
+            if let NodeRef::Group(g) = node.as_ref() {
+                if is_system_group(g) {
+                    continue;
+                }
+                if g.uuid == category.uuid {
+                    self.merge_contents(category, g);
+                } else {
+                    self.merge_group(category, g);
+                }
+            }
+        }
+    }
+
+    /// Merge `incoming` as an entry of `parent`, using the same rules as `merge_group`.
+    fn merge_entry(&mut self, parent: &mut Group, incoming: &Entry) {
+        let title = incoming.get_title().unwrap_or("").to_string();
+        let existing = parent.children.iter_mut().find_map(|node| match node {
+            Node::Entry(e) if is_copy_of(e.uuid, &e.custom_data, incoming.uuid) => Some(e),
+            _ => None,
+        });
+
+        if let Some(existing) = existing {
+            if is_newer(&incoming.times, &existing.times) {
+                let uuid = existing.uuid;
+                *existing = incoming.clone();
+                if uuid != incoming.uuid {
+                    existing.uuid = uuid;
+                    mark_imported_from(&mut existing.custom_data, incoming.uuid);
+                }
+                self.report.updated_entries += 1;
+                self.report
+                    .log
+                    .push(format!("update post '{title}' ({uuid})"));
+            } else {
+                self.report.kept_existing += 1;
+                self.report
+                    .log
+                    .push(format!("keep local post '{title}' ({})", incoming.uuid));
+            }
+            return;
+        }
+
+        let mut entry = incoming.clone();
+        if self.known.contains(&entry.uuid) {
+            entry.uuid = Uuid::new_v4();
+            mark_imported_from(&mut entry.custom_data, incoming.uuid);
+            self.report.remapped += 1;
+            self.report.log.push(format!(
+                "remap post '{title}' {} -> {}",
+                incoming.uuid, entry.uuid
+            ));
+        }
+        self.known.insert(entry.uuid);
+        self.report.added_entries += 1;
+        self.report
+            .log
+            .push(format!("add post '{title}' ({})", entry.uuid));
+        parent.add_child(entry);
+    }
+}
+
+/// Merge the contents of `source` (or one of its groups) into a category of
+/// `target`; `category_id` has to name a category, not a thread or the
+/// system group.
+pub fn import_into_category(
+    target: &mut Database,
+    category_id: &str,
+    source: &Database,
+    source_group_id: Option<&str>,
+) -> Result<ImportReport, String> {
+    let source_group = match source_group_id {
+        Some(id) => find_group_by_id(&source.root, id)
+            .ok_or_else(|| format!("Source group {id} not found"))?,
+        None => &source.root,
+    };
+
+    let mut known = HashSet::new();
+    collect_uuids(&target.root, &mut known);
+
+    let category = target
+        .root
+        .children
+        .iter_mut()
+        .find_map(|node| match node {
+            Node::Group(g) if !is_system_group(g) && g.uuid.to_string() == category_id => Some(g),
+            _ => None,
+        })
+        .ok_or_else(|| format!("Category {category_id} not found"))?;
+
+    let mut report = ImportReport::default();
+    let mut importer = Importer {
+        known,
+        report: &mut report,
+    };
+    importer.merge_contents(category, source_group);
+
+    Ok(report)
+}
@@ -0,0 +1,215 @@
+use std::{io::Write, path::Path};
+
+use clap::ValueEnum;
+use keepass::{
+    config::{DatabaseConfig, KdfConfig},
+    db::Group,
+    Database,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    audit::{record_event, Actor, AUDIT_GROUP, AUDIT_HEAD_GROUP},
+    db::system_subgroup_mut,
+    moderation::{MODERATION_GROUP, REPORTS_GROUP},
+    notifications::NOTIFICATIONS_GROUP,
+    outbox::OUTBOX_GROUP,
+    readstate::READ_STATE_GROUP,
+};
+
+/// Name given to the root group when the template does not set one.
+const DEFAULT_FORUM_NAME: &str = "kdbx-forum";
+
+/// Every system subgroup, created up front so a fresh file already has the
+/// layout the server expects.
+const SYSTEM_SUBGROUPS: [&str; 7] = [
+    READ_STATE_GROUP,
+    NOTIFICATIONS_GROUP,
+    MODERATION_GROUP,
+    REPORTS_GROUP,
+    OUTBOX_GROUP,
+    AUDIT_GROUP,
+    AUDIT_HEAD_GROUP,
+];
+
+/// Key derivation function protecting the master key.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum KdfKind {
+    /// Argon2id, the KeePassXC recommendation
+    #[default]
+    Argon2id,
+    /// Argon2d, the KDBX 4 default
+    Argon2d,
+    /// AES-KDF, for clients without Argon2 support
+    Aes,
+}
+
+/// How expensive it is to derive the key. Every save re-derives it, so
+/// the defaults are lighter than a desktop password manager would pick.
+#[derive(Clone, Copy, Debug)]
+pub struct KdfParams {
+    pub kind: KdfKind,
+    /// Argon2 memory in MiB.
+    pub memory_mib: u64,
+    /// Argon2 passes, or AES rounds.
+    pub iterations: Option<u64>,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    fn kdf_config(&self) -> Result<KdfConfig, String> {
+        // keepass does not re-export argon2, so borrow the version from its default.
+        let KdfConfig::Argon2 { version, .. } = DatabaseConfig::default().kdf_config else {
+            unreachable!("the default KDF is Argon2");
+        };
+        if self.parallelism == 0 {
+            return Err("--kdf-parallelism must be at least 1".to_string());
+        }
+        if self.memory_mib == 0 {
+            return Err("--kdf-memory must be at least 1 MiB".to_string());
+        }
+        let memory = self.memory_mib * 1024 * 1024;
+        Ok(match self.kind {
+            KdfKind::Argon2id => KdfConfig::Argon2id {
+                iterations: self.iterations.unwrap_or(2),
+                memory,
+                parallelism: self.parallelism,
+                version,
+            },
+            KdfKind::Argon2d => KdfConfig::Argon2 {
+                iterations: self.iterations.unwrap_or(2),
+                memory,
+                parallelism: self.parallelism,
+                version,
+            },
+            KdfKind::Aes => KdfConfig::Aes {
+                rounds: self.iterations.unwrap_or(600_000),
+            },
+        })
+    }
+}
+
+/// Starter layout read from `--template`:
+///
+/// ```toml
+/// name = "Our forum"
+///
+/// [[categories]]
+/// name = "General"
+/// description = "Anything goes"
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    /// Name of the root group.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub categories: Vec<TemplateCategory>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateCategory {
+    pub name: String,
+    /// Stored as the group's notes.
+    pub description: Option<String>,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        let category = |name: &str, description: &str| TemplateCategory {
+            name: name.to_string(),
+            description: Some(description.to_string()),
+        };
+        Self {
+            name: None,
+            categories: vec![
+                category("Announcements", "News from the people running the forum"),
+                category("General", "Anything that fits nowhere else"),
+                category("Help", "Questions and answers"),
+            ],
+        }
+    }
+}
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read template {}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid template {}: {e}", path.display()))
+    }
+
+    /// Only the named categories, without descriptions.
+    pub fn from_names(names: &[String]) -> Self {
+        Self {
+            name: None,
+            categories: names
+                .iter()
+                .map(|name| TemplateCategory {
+                    name: name.clone(),
+                    description: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A new database with the template's categories and all system groups.
+pub fn new_forum_database(template: &Template, kdf: &KdfParams) -> Result<Database, String> {
+    let config = DatabaseConfig {
+        kdf_config: kdf.kdf_config()?,
+        ..Default::default()
+    };
+    let mut db = Database::new(config);
+    db.root.name = template
+        .name
+        .clone()
+        .unwrap_or_else(|| DEFAULT_FORUM_NAME.to_string());
+
+    for category in &template.categories {
+        let name = category.name.trim();
+        if name.is_empty() {
+            return Err("Category names must not be empty".to_string());
+        }
+        if name.starts_with('.') {
+            return Err(format!("Category '{name}' must not start with '.'"));
+        }
+        let mut group = Group::new(name);
+        group.notes = category.description.clone();
+        db.root.add_child(group);
+    }
+
+    for name in SYSTEM_SUBGROUPS {
+        system_subgroup_mut(&mut db, name);
+    }
+    let target = db.root.uuid.to_string();
+    let detail = format!("{} categories", template.categories.len());
+    record_event(&mut db, &Actor::local("cli"), "init", &target, Some(&detail));
+    Ok(db)
+}
+
+/// Write a KeePass 2.0 XML key file with 32 random bytes, refusing to
+/// replace an existing file.
+pub fn create_keyfile(path: &Path) -> Result<(), String> {
+    let mut key = [0u8; 32];
+    getrandom::getrandom(&mut key).map_err(|e| format!("Failed to generate a key: {e}"))?;
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02X}")).collect::<String>();
+    let hash = hex(&Sha256::digest(key)[..4]);
+    let data = hex(&key);
+
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <KeyFile>\n\
+         \t<Meta>\n\t\t<Version>2.0</Version>\n\t</Meta>\n\
+         \t<Key>\n\t\t<Data Hash=\"{hash}\">{data}</Data>\n\t</Key>\n\
+         </KeyFile>\n"
+    );
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| format!("Failed to create key file {}: {e}", path.display()))?;
+    file.write_all(xml.as_bytes())
+        .map_err(|e| format!("Failed to write key file {}: {e}", path.display()))
+}
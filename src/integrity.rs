@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use keepass::{
+    db::{Group, NodeRef},
+    Database,
+};
+
+use crate::{
+    db::categories,
+    dto::{GroupDumpDto, IntegrityReportDto, OrphanEntryDto},
+    dump::database_to_dump,
+};
+
+/// Every group and entry keyed by UUID, as (parent UUID, JSON of the node
+/// without its children). Built from the dump form, so it covers all
+/// fields, times and history.
+fn flatten(db: &Database) -> BTreeMap<String, (Option<String>, String)> {
+    fn walk(
+        group: &GroupDumpDto,
+        parent: Option<&str>,
+        out: &mut BTreeMap<String, (Option<String>, String)>,
+    ) {
+        let node = GroupDumpDto {
+            id: group.id.clone(),
+            name: group.name.clone(),
+            notes: group.notes.clone(),
+            times: group.times.clone(),
+            look: group.look.clone(),
+            groups: Vec::new(),
+            posts: Vec::new(),
+            order: Vec::new(),
+        };
+        let json = serde_json::to_string(&node).unwrap_or_default();
+        out.insert(group.id.clone(), (parent.map(str::to_string), json));
+        for post in &group.posts {
+            let json = serde_json::to_string(post).unwrap_or_default();
+            out.insert(post.id.clone(), (Some(group.id.clone()), json));
+        }
+        for child in &group.groups {
+            walk(child, Some(&group.id), out);
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    walk(&database_to_dump(db).root, None, &mut out);
+    out
+}
+
+fn collect_uuids(group: &Group, seen: &mut BTreeSet<String>, duplicates: &mut BTreeSet<String>) {
+    if !seen.insert(group.uuid.to_string()) {
+        duplicates.insert(group.uuid.to_string());
+    }
+    for node in &group.children {
+        match node.as_ref() {
+            NodeRef::Group(g) => collect_uuids(g, seen, duplicates),
+            NodeRef::Entry(e) => {
+                if !seen.insert(e.uuid.to_string()) {
+                    duplicates.insert(e.uuid.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Entries placed directly under `group`, which should hold only groups.
+fn orphans_in(group: &Group, out: &mut Vec<OrphanEntryDto>) {
+    for entry in group.entries() {
+        out.push(OrphanEntryDto {
+            id: entry.uuid.to_string(),
+            parent_id: group.uuid.to_string(),
+        });
+    }
+}
+
+/// Check the structure of the in-memory tree and compare it with the copy
+/// read back from disk (`Err` if that could not be opened). Without `disk`
+/// only the structure is checked, e.g. right after opening the file.
+///
+/// The whole tree takes part in the comparison and the duplicate check,
+/// the system group included; only forum content is checked for orphans
+/// and empty threads.
+pub fn check_integrity(
+    memory: &Database,
+    disk: Option<Result<Database, String>>,
+) -> IntegrityReportDto {
+    let mut report = IntegrityReportDto {
+        ok: true,
+        disk_readable: disk.as_ref().is_none_or(Result::is_ok),
+        disk_error: None,
+        missing_on_disk: Vec::new(),
+        missing_in_memory: Vec::new(),
+        changed: Vec::new(),
+        orphan_entries: Vec::new(),
+        empty_threads: Vec::new(),
+        duplicate_uuids: Vec::new(),
+    };
+
+    match disk {
+        None => {}
+        Some(Ok(disk)) => {
+            let in_memory = flatten(memory);
+            let on_disk = flatten(&disk);
+            for (id, node) in &in_memory {
+                match on_disk.get(id) {
+                    None => report.missing_on_disk.push(id.clone()),
+                    Some(other) if other != node => report.changed.push(id.clone()),
+                    Some(_) => {}
+                }
+            }
+            report.missing_in_memory = on_disk
+                .keys()
+                .filter(|id| !in_memory.contains_key(*id))
+                .cloned()
+                .collect();
+        }
+        Some(Err(e)) => report.disk_error = Some(e),
+    }
+
+    orphans_in(&memory.root, &mut report.orphan_entries);
+    for category in categories(&memory.root) {
+        orphans_in(category, &mut report.orphan_entries);
+        for thread in category.groups() {
+            if thread.entries().is_empty() {
+                report.empty_threads.push(thread.uuid.to_string());
+            }
+        }
+    }
+
+    let mut duplicates = BTreeSet::new();
+    collect_uuids(&memory.root, &mut BTreeSet::new(), &mut duplicates);
+    report.duplicate_uuids = duplicates.into_iter().collect();
+
+    report.ok = report.disk_readable
+        && report.missing_on_disk.is_empty()
+        && report.missing_in_memory.is_empty()
+        && report.changed.is_empty()
+        && report.orphan_entries.is_empty()
+        && report.empty_threads.is_empty()
+        && report.duplicate_uuids.is_empty();
+    report
+}
@@ -0,0 +1,106 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Who holds the lock, written into the lock file for the error message
+/// of the next process that tries to take it.
+#[derive(Serialize, Deserialize, Debug)]
+struct Holder {
+    pid: u32,
+    host: String,
+    command: String,
+    since: String,
+}
+
+/// Exclusive advisory lock (`flock`) on `<file>.lock` next to the database,
+/// held by a running server and by every command that rewrites the file, so
+/// neither saves over the other's changes.
+///
+/// The operating system drops the lock when the process ends, however it
+/// ends, so a lock file left behind by a crash does not block anything. The
+/// file itself stays; only the lock on it counts.
+#[derive(Debug)]
+pub struct LockFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl LockFile {
+    pub fn path_for(db_path: &Path) -> PathBuf {
+        let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        db_path.with_file_name(name)
+    }
+
+    /// Take the lock for `command`, failing if another process holds it.
+    pub fn acquire(db_path: &Path, command: &str) -> Result<Self, String> {
+        let path = Self::path_for(db_path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| {
+                format!(
+                    "Failed to open lock file {}: {e}; a database on a read-only path or \
+                     mount can only be served with --read-only",
+                    path.display()
+                )
+            })?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut text = String::new();
+                let _ = file.read_to_string(&mut text);
+                let holder = match serde_json::from_str::<Holder>(&text) {
+                    Ok(h) => format!(
+                        "`{}` (pid {} on {}, since {})",
+                        h.command, h.pid, h.host, h.since
+                    ),
+                    Err(_) => "another process".to_string(),
+                };
+                return Err(format!(
+                    "{} is already in use by {holder}; stop it first, or serve a second \
+                     copy with --read-only",
+                    db_path.display()
+                ));
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(format!("Failed to lock {}: {e}", path.display()));
+            }
+        }
+
+        let holder = Holder {
+            pid: std::process::id(),
+            host: hostname::get()
+                .map(|h| h.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            command: command.to_string(),
+            since: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        };
+        let written = file
+            .set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| writeln!(file, "{}", serde_json::json!(holder)));
+        if let Err(e) = written {
+            tracing::warn!(error = %e, path = %path.display(), "Failed to write lock file");
+        }
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // Clear the holder before letting go, so nobody is named who has left.
+        if let Err(e) = self.file.set_len(0) {
+            tracing::warn!(error = %e, path = %self.path.display(), "Failed to clear lock file");
+        }
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    fmt,
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderName,
+};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing::{level_filters::LevelFilter, Span};
+use tracing_subscriber::EnvFilter;
+
+/// Header carrying the request ID; set on every request and echoed in the response.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Whether user-supplied text may appear in logs; see `redact`.
+static LOG_CONTENT: AtomicBool = AtomicBool::new(false);
+
+/// How log lines are written to stderr.
+#[derive(Clone, Copy, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Install the global log subscriber.
+///
+/// `level` is a filter directive such as `info` or `kdbx_forum=debug,tower_http=info`;
+/// `RUST_LOG` overrides it when set. `log_content` only takes effect when debug
+/// logging is enabled, so turning the level down always redacts again.
+pub fn init(level: &str, format: LogFormat, log_content: bool) -> Result<(), String> {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.trim().is_empty() => EnvFilter::try_new(directives),
+        _ => EnvFilter::try_new(level),
+    }
+    .map_err(|e| format!("Invalid log level '{level}': {e}"))?;
+
+    let debug = filter
+        .max_level_hint()
+        .is_none_or(|max| max >= LevelFilter::DEBUG);
+    LOG_CONTENT.store(log_content && debug, Ordering::Relaxed);
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    };
+    result.map_err(|e| format!("Failed to set up logging: {e}"))
+}
+
+/// User-supplied text (post titles and bodies, user names, tags, reasons)
+/// as it should appear in a log line.
+///
+/// Only its length is shown, unless content logging was enabled together
+/// with debug logging. UUIDs, counts and statuses are logged as they are.
+pub fn redact(text: &str) -> Redacted<'_> {
+    Redacted(text)
+}
+
+pub struct Redacted<'a>(&'a str);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if LOG_CONTENT.load(Ordering::Relaxed) {
+            write!(f, "{:?}", self.0)
+        } else {
+            write!(f, "<redacted, {} chars>", self.0.chars().count())
+        }
+    }
+}
+
+/// Span wrapping each HTTP request. Only the route pattern is recorded, e.g.
+/// `/tags/:tag/threads`: paths may carry tags or file names, and query
+/// strings feed tokens.
+pub fn request_span(request: &Request) -> Span {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-");
+    tracing::info_span!(
+        "request",
+        id,
+        method = %request.method(),
+        route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or("-", MatchedPath::as_str),
+    )
+}
@@ -1,45 +1,602 @@
+mod app;
+mod archive;
 mod args;
+mod attachments;
+mod audit;
+mod cli;
+mod config;
 mod db;
 mod dto;
+mod dump;
+mod events;
+mod export;
+mod feed;
+mod forums;
+mod import;
+mod init;
+mod integrity;
+mod lockfile;
+mod logging;
+mod metrics;
+mod moderation;
+mod notifications;
+mod outbox;
+mod reactions;
+mod readstate;
 mod routes;
+mod site;
 mod state;
+mod tags;
+mod tls;
 
-use std::error::Error;
-
-use axum::{
-    routing::{get, post},
-    Router,
+use std::{
+    error::Error,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
+
+use axum::{middleware, Router};
+use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::Level;
 
-use args::Args;
-use db::{build_db_key, open_database};
-use routes::{
-    create_reply, create_thread, get_thread_detail, index, list_categories, list_threads_in_category,
+use app::ForumApp;
+use archive::{add_archived_threads, read_archive};
+use args::{Args, Command, InitArgs, ServeArgs, UserCommand};
+use audit::{audit_log, record_event, verify_audit_log, Actor};
+use cli::{post_reply, post_thread, print_categories, print_created, print_thread, print_threads};
+use config::{FileConfig, ServeConfig, DEFAULT_LOG_LEVEL};
+use db::{
+    build_db_key, build_db_key_with_prompt, open_database, prompt_new_password, save_database,
 };
-use state::AppState;
+use dump::{database_from_dump, database_to_dump, parse_dump, write_ndjson, DumpFormat};
+use export::build_export_database;
+use forums::Forums;
+use import::import_into_category;
+use init::{create_keyfile, new_forum_database, KdfParams, Template};
+use integrity::check_integrity;
+use lockfile::LockFile;
+use logging::{request_span, REQUEST_ID_HEADER};
+use moderation::{moderate_user, user_overview, UserAction};
+use routes::trust_user_header;
+use site::export_html_site;
+use tls::{load_server_config, spawn_redirect_listener, spawn_reloader};
+
+const NO_DATABASE: &str =
+    "No database given; pass --database, set KDBX_FORUM_DATABASE or `database` in the config file";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
+    let mut file = FileConfig::load(args.config.as_deref())?;
+    logging::init(
+        args.log_level
+            .as_deref()
+            .or(file.log_level.as_deref())
+            .unwrap_or(DEFAULT_LOG_LEVEL),
+        args.log_format.or(file.log_format).unwrap_or_default(),
+        args.log_content || file.log_content.unwrap_or(false),
+    )?;
+
+    let command = args
+        .command
+        .unwrap_or_else(|| Command::Serve(Box::new(ServeArgs::from_env())));
+    if let Command::Serve(serve_args) = command {
+        return serve(*serve_args, args.database, args.keyfile, args.password, file).await;
+    }
+    let database = match &command {
+        Command::Init(init) => init.path.clone().or(args.database),
+        _ => args.database,
+    };
+    let db_path = database.or(file.database.take()).ok_or(NO_DATABASE)?;
+    let keyfile = args.keyfile.or(file.keyfile.take());
+    let password = args.password.or(file.password.take());
+    if let Command::Init(init) = command {
+        let _lock = LockFile::acquire(&db_path, "init")?;
+        // The key does not exist yet: it may need a new key file and a confirmed password.
+        return init_database(init, &db_path, password, &keyfile);
+    }
+    let key = build_db_key(password, &keyfile)?;
+
+    match command {
+        Command::Restore { input, force } => {
+            let _lock = LockFile::acquire(&db_path, "restore")?;
+            restore_database(&input, force, &db_path, &key)
+        }
+        command => {
+            // Held until the command finishes; reading needs no lock.
+            let _lock = match command.writes_database() {
+                true => Some(LockFile::acquire(&db_path, command.name())?),
+                false => None,
+            };
+            let db = open_database(&db_path, &key)?;
+            run_command(command, db, &db_path, &key, &file)
+        }
+    }
+}
+
+/// Serve one forum, or several when `forums` are configured, until the
+/// process is stopped.
+async fn serve(
+    serve_args: ServeArgs,
+    database: Option<PathBuf>,
+    keyfile: Option<PathBuf>,
+    password: Option<String>,
+    mut file: FileConfig,
+) -> Result<(), Box<dyn Error>> {
+    let db_path = database.or(file.database.take());
+    let keyfile = keyfile.or(file.keyfile.take());
+    let password = password.or(file.password.take());
+    let config = ServeConfig::merge(serve_args, file)?;
+    if !config.forums.is_empty() {
+        if db_path.is_some() || keyfile.is_some() || password.is_some() {
+            return Err("`database`, `keyfile` and `password` are for a single forum; give \
+                        each forum its own under `forums` instead"
+                .into());
+        }
+        return serve_forums(config).await;
+    }
+
+    let db_path = db_path.ok_or(NO_DATABASE)?;
+    let key = build_db_key(password, &keyfile)?;
+    // A read-only instance may run next to the one that writes.
+    let _lock = match config.read_only {
+        true => None,
+        false => Some(LockFile::acquire(&db_path, "serve")?),
+    };
+    let db = open_database(&db_path, &key)?;
+    let forum = ForumApp::start(
+        db,
+        db_path,
+        key,
+        &config,
+        config.read_only,
+        "",
+        &Actor::local("server"),
+        &config.listen,
+    )?;
+    let result = listen(forum.router.clone(), &config).await;
+    // Let a save that is under way finish before the lock is released.
+    forum.stop().await;
+    result
+}
+
+/// Host several forums under `/f/<name>/`, each unlocked and locked on its own.
+async fn serve_forums(config: ServeConfig) -> Result<(), Box<dyn Error>> {
+    let forums = Forums::new(config)?;
+    forums.unlock_configured().await?;
+    let result = listen(forums.router(), forums.config()).await;
+    forums.lock_all().await;
+    result
+}
+
+/// Listen on the configured address, over HTTPS when a certificate is
+/// configured, until the server fails or a shutdown signal arrives.
+async fn listen(app: Router, config: &ServeConfig) -> Result<(), Box<dyn Error>> {
+    let app = app
+        .layer(middleware::from_fn_with_state(
+            Arc::new(config.trusted_proxies.clone()),
+            trust_user_header,
+        ))
+        .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUuid));
+
+    let addr = &config.listen;
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let server = async {
+        let Some(tls) = config.tls.clone() else {
+            tracing::info!("Serving kdbx-forum on http://{addr}");
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            axum::serve(listener, app).await?;
+            return Ok::<(), Box<dyn Error>>(());
+        };
+
+        let rustls_config = RustlsConfig::from_config(Arc::new(load_server_config(&tls)?));
+        spawn_reloader(rustls_config.clone(), tls.clone());
+
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        if let Some(redirect) = &config.http_redirect {
+            spawn_redirect_listener(redirect, listener.local_addr()?).await?;
+        }
+        if tls.client_ca.is_some() {
+            tracing::info!("Serving kdbx-forum on https://{addr} (client certificates required)");
+        } else {
+            tracing::info!("Serving kdbx-forum on https://{addr}");
+        }
+        axum_server::from_tcp_rustls(listener, rustls_config)
+            .serve(app)
+            .await?;
+        Ok(())
+    };
+
+    tokio::select! {
+        result = server => result?,
+        () = shutdown_signal() => tracing::info!("Shutting down"),
+    }
+    Ok(())
+}
+
+/// Resolve on Ctrl-C or SIGTERM, so the lock file is cleaned up on the way out.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    if let Ok(mut terminate) =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        return;
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Execute a one-off subcommand against the unlocked database.
+fn run_command(
+    command: Command,
+    mut db: keepass::Database,
+    db_path: &PathBuf,
+    key: &keepass::DatabaseKey,
+    file: &FileConfig,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Export {
+            output,
+            categories,
+            threads,
+            export_password,
+            export_keyfile,
+        } => {
+            let exported = build_export_database(&db, &categories, &threads)?;
+            let export_key =
+                build_db_key_with_prompt(export_password, &export_keyfile, "Export password: ")?;
+            save_database(&exported, &output, &export_key)?;
+            let detail = format!(
+                "{} categories, {} threads to {}",
+                categories.len(),
+                threads.len(),
+                output.display()
+            );
+            let target = db.root.uuid.to_string();
+            record_event(&mut db, &Actor::local("cli"), "export", &target, Some(&detail));
+            save_database(&db, db_path, key)?;
+            println!(
+                "Exported {} categories and {} threads to {}",
+                categories.len(),
+                threads.len(),
+                output.display()
+            );
+        }
+        Command::Import {
+            source,
+            category,
+            source_group,
+            import_password,
+            import_keyfile,
+            dry_run,
+        } => {
+            let source_key =
+                build_db_key_with_prompt(import_password, &import_keyfile, "Source password: ")?;
+            let source_db = open_database(&source, &source_key)?;
+            let report =
+                import_into_category(&mut db, &category, &source_db, source_group.as_deref())?;
+
+            for line in &report.log {
+                println!("  {line}");
+            }
+            if dry_run {
+                println!("Dry run: {}", report.summary());
+            } else {
+                let detail = format!("{}: {}", source.display(), report.summary());
+                record_event(&mut db, &Actor::local("cli"), "import", &category, Some(&detail));
+                save_database(&db, db_path, key)?;
+                println!("Imported: {}", report.summary());
+            }
+        }
+        Command::ImportArchive {
+            format,
+            input,
+            category,
+            dry_run,
+        } => {
+            let threads = read_archive(format, &input)?;
+            let (thread_count, post_count) =
+                add_archived_threads(&mut db, &category, format, &threads)?;
+            if dry_run {
+                println!("Dry run: would import {thread_count} threads with {post_count} posts");
+            } else {
+                let detail = format!(
+                    "{}: {thread_count} threads, {post_count} posts",
+                    input.display()
+                );
+                let actor = Actor::local("cli");
+                record_event(&mut db, &actor, "import_archive", &category, Some(&detail));
+                save_database(&db, db_path, key)?;
+                println!("Imported {thread_count} threads with {post_count} posts");
+            }
+        }
+        Command::Dump { output, format } => {
+            let dump = database_to_dump(&db);
+            let mut out: Box<dyn std::io::Write> = match &output {
+                Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            match format {
+                DumpFormat::Json => {
+                    serde_json::to_writer_pretty(&mut out, &dump)?;
+                    writeln!(out)?;
+                }
+                DumpFormat::Ndjson => write_ndjson(&dump, &mut out)?,
+            }
+            out.flush()?;
+        }
+        Command::Serve(_) | Command::Init(_) | Command::Restore { .. } => {
+            unreachable!("handled before the database is opened")
+        }
+        Command::Backup { output } => {
+            let output = match output {
+                Some(output) => output,
+                None => default_backup_path(db_path, file.backup_dir.as_deref()),
+            };
+            if output.exists() {
+                return Err(format!("{} already exists", output.display()).into());
+            }
+            if let Some(dir) = output.parent().filter(|d| !d.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            // Copy the file as it is, then prove the copy opens with the same key.
+            std::fs::copy(db_path, &output)?;
+            open_database(&output, key)
+                .map_err(|e| format!("Backup {} does not open: {e}", output.display()))?;
+            println!("Backed up {} to {}", db_path.display(), output.display());
+        }
+        Command::Rekey {
+            new_password,
+            new_keyfile,
+        } => {
+            let new_password = match new_password {
+                Some(password) => password,
+                None => prompt_new_password("New password: ")?,
+            };
+            let new_key = build_db_key(Some(new_password), &new_keyfile)?;
+            let detail = match &new_keyfile {
+                Some(_) => "password and key file",
+                None => "password only",
+            };
+            let target = db.root.uuid.to_string();
+            record_event(&mut db, &Actor::local("cli"), "rekey", &target, Some(detail));
+            // Write the re-keyed copy beside the file and prove it opens with
+            // the new key before it replaces the original.
+            let rekeyed = db_path.with_extension("kdbx.rekey");
+            save_database(&db, &rekeyed, &new_key)?;
+            if let Err(e) = open_database(&rekeyed, &new_key) {
+                let _ = std::fs::remove_file(&rekeyed);
+                return Err(format!("The re-keyed database does not open: {e}").into());
+            }
+            std::fs::rename(&rekeyed, db_path)?;
+            println!("Changed the key of {}", db_path.display());
+        }
+        Command::Check { head } => {
+            let report = check_integrity(&db, None);
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            let audit = verify_audit_log(&db, head.as_deref());
+            if audit.ok {
+                println!("Audit log OK: {} records, head {}", audit.records, audit.head_hash);
+            }
+            if !report.ok || !audit.ok {
+                return Err(match audit.error {
+                    Some(e) => format!("Check failed: {e}"),
+                    None => "Check found problems in the database structure".to_string(),
+                }
+                .into());
+            }
+        }
+        Command::User { command } => run_user_command(command, &mut db, db_path, key, file)?,
+        Command::Categories { format } => print_categories(&db, format)?,
+        Command::Threads { category, format } => print_threads(&db, &category, format)?,
+        Command::Show { thread, format } => print_thread(&db, &thread, format)?,
+        Command::Post {
+            category,
+            title,
+            author,
+            body,
+            tags,
+            format,
+        } => {
+            let body = read_body(body)?;
+            let moderators = file.moderators.clone().unwrap_or_default();
+            let (id, held) =
+                post_thread(&mut db, &category, &title, &author, &body, &tags, &moderators)?;
+            save_database(&db, db_path, key)?;
+            print_created(&id, held, format)?;
+        }
+        Command::Reply {
+            thread,
+            author,
+            body,
+            format,
+        } => {
+            let body = read_body(body)?;
+            let moderators = file.moderators.clone().unwrap_or_default();
+            let (id, held) = post_reply(&mut db, &thread, &author, &body, &moderators)?;
+            save_database(&db, db_path, key)?;
+            print_created(&id, held, format)?;
+        }
+        Command::ExportHtml { output } => {
+            let pages = export_html_site(&db, &output)?;
+            println!("Wrote {pages} pages to {}", output.display());
+        }
+        Command::VerifyAudit { head, list } => {
+            if list {
+                for record in audit_log(&db, None).iter().rev() {
+                    println!("{}", serde_json::to_string(record)?);
+                }
+            }
+            let result = verify_audit_log(&db, head.as_deref());
+            if !result.ok {
+                return Err(format!(
+                    "Audit log is broken after {} of {} records: {}",
+                    result.head_seq,
+                    result.records,
+                    result.error.unwrap_or_default()
+                )
+                .into());
+            }
+            println!(
+                "Audit log OK: {} records, head {} {}",
+                result.records, result.head_seq, result.head_hash
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The post text given with `--body`, or else all of standard input.
+fn read_body(body: Option<String>) -> Result<String, Box<dyn Error>> {
+    match body {
+        Some(body) => Ok(body),
+        None => Ok(std::io::read_to_string(std::io::stdin())?
+            .trim_end_matches(['\r', '\n'])
+            .to_string()),
+    }
+}
+
+/// `<dir>/<database name>-<UTC time>.kdbx`, where `dir` defaults to the
+/// database's own directory.
+fn default_backup_path(db_path: &Path, backup_dir: Option<&Path>) -> PathBuf {
+    let stem = db_path
+        .file_stem()
+        .map_or_else(|| "forum".into(), |s| s.to_string_lossy());
+    let name = format!("{stem}-{}.kdbx", chrono::Utc::now().format("%Y%m%dT%H%M%SZ"));
+    match backup_dir.or_else(|| db_path.parent()) {
+        Some(dir) => dir.join(name),
+        None => PathBuf::from(name),
+    }
+}
 
-    let key = build_db_key(args.password.clone(), &args.keyfile)?;
-    let db = open_database(&args.database, &key)?;
-    let state = AppState::new(db, args.database.clone(), key);
+/// List users or apply a moderation action to one.
+fn run_user_command(
+    command: UserCommand,
+    db: &mut keepass::Database,
+    db_path: &PathBuf,
+    key: &keepass::DatabaseKey,
+    file: &FileConfig,
+) -> Result<(), Box<dyn Error>> {
+    let (action, target) = match command {
+        UserCommand::List => {
+            let moderators = file.moderators.clone().unwrap_or_default();
+            for user in user_overview(db, &moderators) {
+                let mut roles = Vec::new();
+                if user.moderator {
+                    roles.push("moderator".to_string());
+                }
+                if user.trusted {
+                    roles.push("trusted".to_string());
+                }
+                if user.banned {
+                    roles.push("banned".to_string());
+                }
+                if user.warnings > 0 {
+                    roles.push(format!("{} warnings", user.warnings));
+                }
+                println!("{}\t{} posts\t{}", user.name, user.posts, roles.join(", "));
+            }
+            return Ok(());
+        }
+        UserCommand::Warn(target) => (UserAction::Warn, target),
+        UserCommand::Ban(target) => (UserAction::Ban, target),
+        UserCommand::Unban(target) => (UserAction::Unban, target),
+        UserCommand::Trust(target) => (UserAction::Trust, target),
+        UserCommand::Untrust(target) => (UserAction::Untrust, target),
+    };
+    moderate_user(db, &target.name, action, &Actor::local("cli"), target.reason.as_deref())?;
+    save_database(db, db_path, key)?;
+    println!("Applied '{}' to {}", action.name(), target.name);
+    Ok(())
+}
 
-    let app = Router::new()
-        .route("/", get(index))
-        .route("/categories", get(list_categories))
-        .route("/categories/:id/threads", get(list_threads_in_category))
-        .route("/threads/:id", get(get_thread_detail))
-        .route("/threads", post(create_thread))
-        .route("/threads/:id/replies", post(create_reply))
-        .with_state(state);
+/// Build a new forum database at `db_path` with starter categories.
+fn init_database(
+    init: InitArgs,
+    db_path: &PathBuf,
+    password: Option<String>,
+    keyfile: &Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    if db_path.exists() && !init.force {
+        return Err(format!(
+            "{} already exists; pass --force to overwrite it",
+            db_path.display()
+        )
+        .into());
+    }
+
+    let template = match &init.template {
+        Some(path) => Template::load(path)?,
+        None if !init.categories.is_empty() => Template::from_names(&init.categories),
+        None => Template::default(),
+    };
+    let kdf = KdfParams {
+        kind: init.kdf,
+        memory_mib: init.kdf_memory,
+        iterations: init.kdf_iterations,
+        parallelism: init.kdf_parallelism,
+    };
+    let db = new_forum_database(&template, &kdf)?;
+
+    if init.create_keyfile {
+        let path = keyfile.as_ref().ok_or("--create-keyfile needs --keyfile")?;
+        create_keyfile(path)?;
+        println!("Created key file {}", path.display());
+    }
+    let password = match password {
+        Some(password) => password,
+        None => prompt_new_password("New master password: ")?,
+    };
+    let key = build_db_key(Some(password), keyfile)?;
+    save_database(&db, db_path, &key)?;
+    println!(
+        "Created {} with {} categories",
+        db_path.display(),
+        template.categories.len()
+    );
+    Ok(())
+}
 
-    let addr = &args.listen;
-    println!("Serving kdbx-forum on http://{addr}");
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+/// Build a new database at `db_path` from a dump file.
+fn restore_database(
+    input: &PathBuf,
+    force: bool,
+    db_path: &PathBuf,
+    key: &keepass::DatabaseKey,
+) -> Result<(), Box<dyn Error>> {
+    if db_path.exists() && !force {
+        return Err(format!(
+            "{} already exists; pass --force to overwrite it",
+            db_path.display()
+        )
+        .into());
+    }
 
+    let text = std::fs::read_to_string(input)?;
+    let dump = parse_dump(&text)?;
+    let mut db = database_from_dump(&dump)?;
+    let target = db.root.uuid.to_string();
+    let detail = input.display().to_string();
+    record_event(&mut db, &Actor::local("cli"), "restore", &target, Some(&detail));
+    save_database(&db, db_path, key)?;
+    println!("Restored {} into {}", input.display(), db_path.display());
     Ok(())
 }
@@ -0,0 +1,191 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+/// Upper bounds, in seconds, of the latency histogram buckets. Saves
+/// re-derive the key with Argon2, so the buckets reach well past a second.
+const BUCKETS: [f64; 14] = [
+    0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Cumulative histogram in the Prometheus sense.
+#[derive(Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bound, count) in BUCKETS.iter().zip(&mut self.counts) {
+            if secs <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    /// Append the `_bucket`, `_sum` and `_count` series. `labels` is either
+    /// empty or a comma-terminated label list such as `route="/",`.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (bound, count) in BUCKETS.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{name}_bucket{{{labels}le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels}le=\"+Inf\"}} {}", self.count);
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{labels}}}"),
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+/// Quote a label value as the exposition format wants it.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Process-wide counters and latency histograms, rendered by `GET /metrics`.
+///
+/// Gauges that describe the database itself (file size, post counts) are
+/// computed when scraped; see `routes::metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    /// (method, route) → latency; route is the matched pattern, not the path.
+    request_latency: Mutex<BTreeMap<(String, String), Histogram>>,
+    /// (method, route, status) → number of responses.
+    responses: Mutex<BTreeMap<(String, String, u16), u64>>,
+    /// `read` / `write` → time spent waiting for the database lock.
+    lock_wait: Mutex<BTreeMap<&'static str, Histogram>>,
+    saves: Mutex<Histogram>,
+    save_failures: AtomicU64,
+}
+
+impl Metrics {
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        if let Ok(mut latency) = self.request_latency.lock() {
+            latency
+                .entry((method.to_string(), route.to_string()))
+                .or_default()
+                .observe(elapsed);
+        }
+        if let Ok(mut responses) = self.responses.lock() {
+            *responses
+                .entry((method.to_string(), route.to_string(), status))
+                .or_default() += 1;
+        }
+    }
+
+    pub fn observe_lock_wait(&self, mode: &'static str, waited: Duration) {
+        if let Ok(mut lock_wait) = self.lock_wait.lock() {
+            lock_wait.entry(mode).or_default().observe(waited);
+        }
+    }
+
+    /// Record one `save_database` call, successful or not.
+    pub fn observe_save(&self, elapsed: Duration, ok: bool) {
+        if let Ok(mut saves) = self.saves.lock() {
+            saves.observe(elapsed);
+        }
+        if !ok {
+            self.save_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The collected metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP kdbx_forum_http_requests_total HTTP responses by route and status.\n");
+        out.push_str("# TYPE kdbx_forum_http_requests_total counter\n");
+        if let Ok(responses) = self.responses.lock() {
+            for ((method, route, status), count) in responses.iter() {
+                let _ = writeln!(
+                    out,
+                    "kdbx_forum_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{status}\"}} {count}",
+                    label(method),
+                    label(route)
+                );
+            }
+        }
+
+        let name = "kdbx_forum_http_request_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Time to produce a response, by route.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        if let Ok(latency) = self.request_latency.lock() {
+            for ((method, route), histogram) in latency.iter() {
+                let labels = format!("method=\"{}\",route=\"{}\",", label(method), label(route));
+                histogram.render(&mut out, name, &labels);
+            }
+        }
+
+        let name = "kdbx_forum_db_lock_wait_seconds";
+        let _ = writeln!(out, "# HELP {name} Time spent waiting for the database lock.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        if let Ok(lock_wait) = self.lock_wait.lock() {
+            for (mode, histogram) in lock_wait.iter() {
+                histogram.render(&mut out, name, &format!("mode=\"{mode}\","));
+            }
+        }
+
+        let name = "kdbx_forum_db_save_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Time to encrypt and write the database file.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        if let Ok(saves) = self.saves.lock() {
+            saves.render(&mut out, name, "");
+        }
+
+        out.push_str("# HELP kdbx_forum_db_save_failures_total Saves that returned an error.\n");
+        out.push_str("# TYPE kdbx_forum_db_save_failures_total counter\n");
+        let _ = writeln!(
+            out,
+            "kdbx_forum_db_save_failures_total {}",
+            self.save_failures.load(Ordering::Relaxed)
+        );
+
+        out
+    }
+}
+
+/// Middleware counting and timing every routed request.
+pub async fn track_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "-".to_string(), |p| p.as_str().to_string());
+    let started = Instant::now();
+    let response = next.run(request).await;
+    metrics.observe_request(&method, &route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+/// Append a single-sample gauge.
+pub fn render_gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
@@ -0,0 +1,673 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use keepass::{
+    db::{Entry, Group, Node, NodeRef, Times, Value},
+    Database,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        categories, entry_to_post_dto, find_entry_by_id, find_entry_by_id_mut, find_group_by_id,
+        find_group_by_id_mut, set_system_record, system_record, system_records,
+    },
+    audit::{record_event, Actor},
+    dto::{ModerationSettingsDto, NotificationDto, QueuedPostDto, ReportDto, UserStandingDto},
+    events::ForumEvent,
+    export::find_category_of,
+    notifications::{load_inbox, record_new_post, store_inbox},
+};
+
+/// System subgroup holding the `settings` record (JSON `ModerationSettingsDto`).
+pub const MODERATION_GROUP: &str = "moderation";
+/// System subgroup holding one entry per report, titled with its id.
+pub const REPORTS_GROUP: &str = "reports";
+
+/// Custom field marking a post as `pending` approval or `hidden` by a moderator.
+pub const MODERATION_FIELD: &str = "Moderation";
+/// Custom field on the opening post marking its thread as locked.
+pub const LOCKED_FIELD: &str = "Locked";
+
+const PENDING: &str = "pending";
+const HIDDEN: &str = "hidden";
+const SETTINGS_KEY: &str = "settings";
+const MAX_REASON_LENGTH: usize = 500;
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+fn now() -> String {
+    Times::now().format(TIME_FORMAT).to_string()
+}
+
+pub fn load_settings(db: &Database) -> ModerationSettingsDto {
+    system_record(db, MODERATION_GROUP, SETTINGS_KEY)
+        .and_then(|notes| serde_json::from_str(notes).ok())
+        .unwrap_or_default()
+}
+
+fn store_settings(db: &mut Database, settings: &ModerationSettingsDto) {
+    let notes = serde_json::to_string(settings).unwrap_or_default();
+    set_system_record(db, MODERATION_GROUP, SETTINGS_KEY, notes);
+}
+
+/// Moderation status of a post, `None` when it is published.
+pub fn post_status(entry: &Entry) -> Option<&str> {
+    entry.get(MODERATION_FIELD).filter(|s| !s.is_empty())
+}
+
+/// Whether a post is visible to everyone.
+pub fn is_published(entry: &Entry) -> bool {
+    post_status(entry).is_none()
+}
+
+/// Whether `viewer` may see a post: moderators see everything, authors
+/// also see their own posts while they wait for approval.
+pub fn can_see(entry: &Entry, viewer: Option<&str>, moderator: bool) -> bool {
+    match post_status(entry) {
+        None => true,
+        Some(_) if moderator => true,
+        Some(PENDING) => viewer.is_some_and(|v| entry.get_username() == Some(v)),
+        Some(_) => false,
+    }
+}
+
+/// Whether a thread has been locked by a moderator.
+pub fn is_thread_locked(thread: &Group) -> bool {
+    thread
+        .entries()
+        .first()
+        .is_some_and(|e| e.get(LOCKED_FIELD) == Some("true"))
+}
+
+/// Whether a moderator closed a category to new threads and replies.
+pub fn is_category_locked(db: &Database, category_id: &str) -> bool {
+    load_settings(db).locked_categories.contains(category_id)
+}
+
+/// Why a thread takes no more activity: it or its category is locked.
+pub fn thread_lock_reason(db: &Database, thread: &Group) -> Option<&'static str> {
+    if is_thread_locked(thread) {
+        return Some("Thread is locked");
+    }
+    find_category_of(&db.root, &thread.uuid.to_string())
+        .is_some_and(|c| is_category_locked(db, &c.uuid.to_string()))
+        .then_some("Category is locked")
+}
+
+/// Why the post `post_id` takes no more reactions or votes, see
+/// [`thread_lock_reason`].
+pub fn post_lock_reason(db: &Database, post_id: &str) -> Option<&'static str> {
+    thread_of_post(db, post_id).and_then(|(thread, _)| thread_lock_reason(db, thread))
+}
+
+/// Whether `user` is banned. A ban names a user, so it only holds when a
+/// trusted proxy sets the user header; otherwise another name gets around it.
+pub fn is_banned(db: &Database, user: &str) -> bool {
+    load_settings(db).banned_users.contains(user.trim())
+}
+
+/// Whether a new post by `author` in `category_id` has to wait for approval.
+pub fn requires_approval(db: &Database, category_id: &str, author: &str, moderator: bool) -> bool {
+    let settings = load_settings(db);
+    let author = author.trim();
+    settings.approval_categories.contains(category_id)
+        && !moderator
+        && !settings.trusted_users.contains(author)
+}
+
+/// Mark a freshly added post as waiting for approval.
+pub fn hold_post(entry: &mut Entry) {
+    entry
+        .fields
+        .insert(MODERATION_FIELD.to_string(), Value::Unprotected(PENDING.to_string()));
+}
+
+fn clean_reason(reason: &str) -> Result<Option<String>, String> {
+    let reason = reason.trim();
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(format!("Reason is longer than {MAX_REASON_LENGTH} characters"));
+    }
+    Ok((!reason.is_empty()).then(|| reason.to_string()))
+}
+
+/// The group directly holding the entry `post_id`, searching below `group`.
+fn parent_of_post<'a>(group: &'a Group, post_id: &str) -> Option<&'a Group> {
+    for node in &group.children {
+        match node.as_ref() {
+            NodeRef::Entry(e) if e.uuid.to_string() == post_id => return Some(group),
+            NodeRef::Group(g) => {
+                if let Some(found) = parent_of_post(g, post_id) {
+                    return Some(found);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The thread holding a post, and whether the post opens it.
+fn thread_of_post<'a>(db: &'a Database, post_id: &str) -> Option<(&'a Group, bool)> {
+    let thread = categories(&db.root).find_map(|c| parent_of_post(c, post_id))?;
+    let starts_thread = thread
+        .entries()
+        .first()
+        .is_some_and(|e| e.uuid.to_string() == post_id);
+    Some((thread, starts_thread))
+}
+
+/// Remove the entry `post_id` from wherever it is below `group`.
+fn remove_post(group: &mut Group, post_id: &str) -> bool {
+    if let Some(idx) = group
+        .children
+        .iter()
+        .position(|node| matches!(node, Node::Entry(e) if e.uuid.to_string() == post_id))
+    {
+        group.children.remove(idx);
+        return true;
+    }
+    group.children.iter_mut().any(|node| match node {
+        Node::Group(g) => remove_post(g, post_id),
+        Node::Entry(_) => false,
+    })
+}
+
+/// Remove the group `group_id` from wherever it is below `group`.
+fn remove_group(group: &mut Group, group_id: &str) -> bool {
+    if let Some(idx) = group
+        .children
+        .iter()
+        .position(|node| matches!(node, Node::Group(g) if g.uuid.to_string() == group_id))
+    {
+        group.children.remove(idx);
+        return true;
+    }
+    group.children.iter_mut().any(|node| match node {
+        Node::Group(g) => remove_group(g, group_id),
+        Node::Entry(_) => false,
+    })
+}
+
+/// File a report about a post. Reporting the same post twice while the
+/// first report is open returns the existing report's id.
+pub fn file_report(
+    db: &mut Database,
+    post_id: &str,
+    reporter: &str,
+    reason: &str,
+) -> Result<String, String> {
+    let (thread, _) = thread_of_post(db, post_id).ok_or_else(|| "Post not found".to_string())?;
+    let thread_id = thread.uuid.to_string();
+    let thread_title = thread.name.clone();
+    let reason = clean_reason(reason)?.unwrap_or_default();
+
+    if let Some(existing) = reports(db, false)
+        .into_iter()
+        .find(|r| r.post_id == post_id && r.reporter == reporter)
+    {
+        return Ok(existing.id);
+    }
+
+    let report = ReportDto {
+        id: Uuid::new_v4().to_string(),
+        post_id: post_id.to_string(),
+        thread_id,
+        thread_title,
+        reporter: reporter.to_string(),
+        reason,
+        created_at: now(),
+        resolution: None,
+        resolved_by: None,
+    };
+    store_report(db, &report);
+    Ok(report.id)
+}
+
+fn store_report(db: &mut Database, report: &ReportDto) {
+    let notes = serde_json::to_string(report).unwrap_or_default();
+    set_system_record(db, REPORTS_GROUP, &report.id, notes);
+}
+
+/// Reports, newest first; resolved ones only with `include_resolved`.
+pub fn reports(db: &Database, include_resolved: bool) -> Vec<ReportDto> {
+    let mut out: Vec<ReportDto> = system_records(db, REPORTS_GROUP)
+        .into_iter()
+        .filter_map(|(_, notes)| serde_json::from_str::<ReportDto>(notes).ok())
+        .filter(|r| include_resolved || r.resolution.is_none())
+        .collect();
+    out.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    out
+}
+
+/// Close every open report about a post.
+fn resolve_reports(db: &mut Database, post_id: &str, resolution: &str, moderator: &Actor) {
+    for mut report in reports(db, false) {
+        if report.post_id == post_id {
+            report.resolution = Some(resolution.to_string());
+            report.resolved_by = Some(moderator.name.clone());
+            store_report(db, &report);
+        }
+    }
+}
+
+/// Close a report without acting on the post.
+pub fn dismiss_report(
+    db: &mut Database,
+    report_id: &str,
+    moderator: &Actor,
+    reason: Option<&str>,
+) -> Result<(), String> {
+    let mut report: ReportDto = system_record(db, REPORTS_GROUP, report_id)
+        .and_then(|notes| serde_json::from_str(notes).ok())
+        .ok_or_else(|| "Report not found".to_string())?;
+    if report.resolution.is_some() {
+        return Err("Report is already resolved".to_string());
+    }
+    let reason = clean_reason(reason.unwrap_or(""))?;
+    report.resolution = Some("dismiss".to_string());
+    report.resolved_by = Some(moderator.name.clone());
+    store_report(db, &report);
+    record_event(db, moderator, "dismiss_report", report_id, reason.as_deref());
+    Ok(())
+}
+
+/// Every post waiting for approval, oldest first.
+pub fn pending_posts(db: &Database) -> Vec<QueuedPostDto> {
+    let mut out = Vec::new();
+    for category in categories(&db.root) {
+        for node in &category.children {
+            let NodeRef::Group(thread) = node.as_ref() else {
+                continue;
+            };
+            for entry in thread.entries() {
+                if post_status(entry) == Some(PENDING) {
+                    out.push(QueuedPostDto {
+                        category_id: category.uuid.to_string(),
+                        thread_id: thread.uuid.to_string(),
+                        thread_title: thread.name.clone(),
+                        post: entry_to_post_dto(entry, None),
+                    });
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Something a moderator can do to a post.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PostAction {
+    Approve,
+    Hide,
+    Unhide,
+    Delete,
+}
+
+impl PostAction {
+    pub fn parse(action: &str) -> Result<Self, String> {
+        match action {
+            "approve" => Ok(Self::Approve),
+            "hide" => Ok(Self::Hide),
+            "unhide" => Ok(Self::Unhide),
+            "delete" => Ok(Self::Delete),
+            _ => Err(format!("Unknown post action '{action}'")),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Approve => "approve",
+            Self::Hide => "hide",
+            Self::Unhide => "unhide",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// Apply a moderator's action to a post, resolve the open reports about it
+/// and record the action in the audit log.
+///
+/// Deleting the opening post deletes the whole thread. Approving a post
+/// publishes it, and the notifications held back while it was pending are
+/// recorded now. Approving, deleting and hiding return an event for the
+/// caller to announce.
+pub fn moderate_post(
+    db: &mut Database,
+    post_id: &str,
+    action: PostAction,
+    moderator: &Actor,
+    reason: Option<&str>,
+) -> Result<Option<ForumEvent>, String> {
+    let reason = clean_reason(reason.unwrap_or(""))?;
+    let (thread, starts_thread) =
+        thread_of_post(db, post_id).ok_or_else(|| "Post not found".to_string())?;
+    let thread_id = thread.uuid.to_string();
+    let category_id = find_category_of(&db.root, &thread_id).map(|c| c.uuid.to_string());
+
+    let mut event = None;
+    match action {
+        PostAction::Delete => {
+            if starts_thread {
+                remove_group(&mut db.root, &thread_id);
+            } else {
+                remove_post(&mut db.root, post_id);
+            }
+        }
+        PostAction::Hide | PostAction::Unhide | PostAction::Approve => {
+            let entry = find_entry_by_id_mut(&mut db.root, post_id)
+                .ok_or_else(|| "Post not found".to_string())?;
+            let status = post_status(entry).map(str::to_string);
+            match (action, status.as_deref()) {
+                (PostAction::Hide, Some(HIDDEN)) => return Err("Post is already hidden".into()),
+                (PostAction::Hide, _) => {
+                    entry
+                        .fields
+                        .insert(MODERATION_FIELD.to_string(), Value::Unprotected(HIDDEN.into()));
+                }
+                (PostAction::Unhide, Some(HIDDEN)) => {
+                    entry.fields.remove(MODERATION_FIELD);
+                }
+                (PostAction::Unhide, _) => return Err("Post is not hidden".into()),
+                (PostAction::Approve, Some(PENDING)) => {
+                    entry.fields.remove(MODERATION_FIELD);
+                }
+                (PostAction::Approve, _) => return Err("Post is not awaiting approval".into()),
+                (PostAction::Delete, _) => unreachable!(),
+            }
+            entry.times.set_last_modification(Times::now());
+
+            if action == PostAction::Approve {
+                let author = entry.get_username().unwrap_or("").trim().to_string();
+                let body = entry.get("Notes").unwrap_or("").to_string();
+                record_new_post(db, &thread_id, post_id, &author, &body, starts_thread);
+                event = published_event(db, &thread_id, post_id, starts_thread);
+            }
+        }
+    }
+    if matches!(action, PostAction::Delete | PostAction::Hide) {
+        event = category_id
+            .map(|category_id| removed_event(db, category_id, &thread_id, post_id, starts_thread));
+    }
+
+    resolve_reports(db, post_id, action.name(), moderator);
+    if action == PostAction::Delete && starts_thread {
+        record_event(db, moderator, "delete_thread", &thread_id, reason.as_deref());
+    } else {
+        let name = format!("{}_post", action.name());
+        record_event(db, moderator, &name, post_id, reason.as_deref());
+    }
+    Ok(event)
+}
+
+/// The event announcing a post that was just published.
+fn published_event(
+    db: &Database,
+    thread_id: &str,
+    post_id: &str,
+    starts_thread: bool,
+) -> Option<ForumEvent> {
+    let category = find_category_of(&db.root, thread_id)?;
+    let thread = find_group_by_id(&db.root, thread_id)?;
+    let post_count = thread.entries().into_iter().filter(|e| is_published(e)).count();
+    Some(if starts_thread {
+        ForumEvent::ThreadCreated {
+            category_id: category.uuid.to_string(),
+            thread_id: thread_id.to_string(),
+            post_id: post_id.to_string(),
+            title: thread.name.clone(),
+            post_count,
+        }
+    } else {
+        ForumEvent::ReplyCreated {
+            category_id: category.uuid.to_string(),
+            thread_id: thread_id.to_string(),
+            post_id: post_id.to_string(),
+            post_count,
+        }
+    })
+}
+
+/// The event announcing a post that was just deleted or hidden.
+fn removed_event(
+    db: &Database,
+    category_id: String,
+    thread_id: &str,
+    post_id: &str,
+    starts_thread: bool,
+) -> ForumEvent {
+    if starts_thread {
+        return ForumEvent::ThreadRemoved {
+            category_id,
+            thread_id: thread_id.to_string(),
+            post_id: post_id.to_string(),
+        };
+    }
+    let post_count = find_group_by_id(&db.root, thread_id)
+        .map_or(0, |t| t.entries().into_iter().filter(|e| is_published(e)).count());
+    ForumEvent::PostRemoved {
+        category_id,
+        thread_id: thread_id.to_string(),
+        post_id: post_id.to_string(),
+        post_count,
+    }
+}
+
+/// Lock or unlock a thread.
+pub fn set_thread_locked(
+    db: &mut Database,
+    thread_id: &str,
+    locked: bool,
+    moderator: &Actor,
+    reason: Option<&str>,
+) -> Result<(), String> {
+    let reason = clean_reason(reason.unwrap_or(""))?;
+    let thread = find_group_by_id_mut(&mut db.root, thread_id)
+        .ok_or_else(|| "Thread not found".to_string())?;
+    let Some(Node::Entry(entry)) = thread
+        .children
+        .iter_mut()
+        .find(|node| matches!(node, Node::Entry(_)))
+    else {
+        return Err("Thread has no posts".to_string());
+    };
+    if locked {
+        entry
+            .fields
+            .insert(LOCKED_FIELD.to_string(), Value::Unprotected("true".to_string()));
+    } else {
+        entry.fields.remove(LOCKED_FIELD);
+    }
+    entry.times.set_last_modification(Times::now());
+
+    let action = if locked { "lock_thread" } else { "unlock_thread" };
+    record_event(db, moderator, action, thread_id, reason.as_deref());
+    Ok(())
+}
+
+/// Require approval of posts by untrusted users in a category, or stop requiring it.
+pub fn set_category_approval(
+    db: &mut Database,
+    category_id: &str,
+    required: bool,
+    moderator: &Actor,
+) -> Result<(), String> {
+    if !categories(&db.root).any(|c| c.uuid.to_string() == category_id) {
+        return Err("Category not found".to_string());
+    }
+    let mut settings = load_settings(db);
+    if required {
+        settings.approval_categories.insert(category_id.to_string());
+    } else {
+        settings.approval_categories.remove(category_id);
+    }
+    store_settings(db, &settings);
+    let action = if required {
+        "require_approval"
+    } else {
+        "stop_requiring_approval"
+    };
+    record_event(db, moderator, action, category_id, None);
+    Ok(())
+}
+
+/// Close a category to new threads and replies, or open it again.
+pub fn set_category_locked(
+    db: &mut Database,
+    category_id: &str,
+    locked: bool,
+    moderator: &Actor,
+) -> Result<(), String> {
+    if !categories(&db.root).any(|c| c.uuid.to_string() == category_id) {
+        return Err("Category not found".to_string());
+    }
+    let mut settings = load_settings(db);
+    if locked {
+        settings.locked_categories.insert(category_id.to_string());
+    } else {
+        settings.locked_categories.remove(category_id);
+    }
+    store_settings(db, &settings);
+    let action = if locked {
+        "lock_category"
+    } else {
+        "unlock_category"
+    };
+    record_event(db, moderator, action, category_id, None);
+    Ok(())
+}
+
+/// Something a moderator can do to a user.
+#[derive(Clone, Copy)]
+pub enum UserAction {
+    Warn,
+    Ban,
+    Unban,
+    Trust,
+    Untrust,
+}
+
+impl UserAction {
+    pub fn parse(action: &str) -> Result<Self, String> {
+        match action {
+            "warn" => Ok(Self::Warn),
+            "ban" => Ok(Self::Ban),
+            "unban" => Ok(Self::Unban),
+            "trust" => Ok(Self::Trust),
+            "untrust" => Ok(Self::Untrust),
+            _ => Err(format!("Unknown user action '{action}'")),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Warn => "warn",
+            Self::Ban => "ban",
+            Self::Unban => "unban",
+            Self::Trust => "trust",
+            Self::Untrust => "untrust",
+        }
+    }
+}
+
+/// Everyone who wrote a post or has moderation state, by name, with their
+/// standing. `moderators` are the configured moderators.
+pub fn user_overview(db: &Database, moderators: &[String]) -> Vec<UserStandingDto> {
+    fn count_posts(group: &Group, posts: &mut BTreeMap<String, usize>) {
+        for node in &group.children {
+            match node.as_ref() {
+                NodeRef::Entry(e) => {
+                    if let Some(author) = e.get_username().filter(|a| !a.is_empty()) {
+                        *posts.entry(author.to_string()).or_default() += 1;
+                    }
+                }
+                NodeRef::Group(g) => count_posts(g, posts),
+            }
+        }
+    }
+
+    let mut posts = BTreeMap::new();
+    for category in categories(&db.root) {
+        count_posts(category, &mut posts);
+    }
+    let settings = load_settings(db);
+    let names: BTreeSet<&String> = posts
+        .keys()
+        .chain(moderators)
+        .chain(&settings.trusted_users)
+        .chain(&settings.banned_users)
+        .chain(settings.warnings.keys())
+        .collect();
+
+    names
+        .into_iter()
+        .map(|name| UserStandingDto {
+            name: name.clone(),
+            posts: posts.get(name).copied().unwrap_or(0),
+            moderator: moderators.contains(name),
+            trusted: settings.trusted_users.contains(name),
+            banned: settings.banned_users.contains(name),
+            warnings: settings.warnings.get(name).copied().unwrap_or(0),
+        })
+        .collect()
+}
+
+/// Apply a moderator's action to a user and record it in the audit log.
+///
+/// A warning is also delivered to the user's notification inbox.
+pub fn moderate_user(
+    db: &mut Database,
+    user: &str,
+    action: UserAction,
+    moderator: &Actor,
+    reason: Option<&str>,
+) -> Result<(), String> {
+    let user = user.trim();
+    if user.is_empty() || user == "Anonymous" {
+        return Err("A user name is required".to_string());
+    }
+    let reason = clean_reason(reason.unwrap_or(""))?;
+
+    let mut settings = load_settings(db);
+    match action {
+        UserAction::Warn => {
+            *settings.warnings.entry(user.to_string()).or_default() += 1;
+            let mut inbox = load_inbox(db, user);
+            inbox.notifications.insert(
+                0,
+                NotificationDto {
+                    id: Uuid::new_v4().to_string(),
+                    kind: "warning".to_string(),
+                    thread_id: String::new(),
+                    thread_title: String::new(),
+                    post_id: String::new(),
+                    author: moderator.name.clone(),
+                    created_at: now(),
+                    read: false,
+                    message: reason.clone(),
+                },
+            );
+            store_inbox(db, user, inbox);
+        }
+        UserAction::Ban => {
+            settings.banned_users.insert(user.to_string());
+            settings.trusted_users.remove(user);
+        }
+        UserAction::Unban => {
+            settings.banned_users.remove(user);
+        }
+        UserAction::Trust => {
+            settings.trusted_users.insert(user.to_string());
+        }
+        UserAction::Untrust => {
+            settings.trusted_users.remove(user);
+        }
+    }
+    store_settings(db, &settings);
+    record_event(db, moderator, &format!("{}_user", action.name()), user, reason.as_deref());
+    Ok(())
+}
+
+/// Whether the post `post_id` exists and is visible to `viewer`.
+pub fn post_visible_to(db: &Database, post_id: &str, viewer: Option<&str>, moderator: bool) -> bool {
+    find_entry_by_id(&db.root, post_id).is_some_and(|e| can_see(e, viewer, moderator))
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeSet;
+
+use keepass::{
+    db::{Group, NodeRef, Times},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::{categories, find_group_by_id, set_system_record, system_record, system_records},
+    dto::{NotificationDto, NotificationsDto},
+};
+
+/// System subgroup holding one entry per user, titled with the user name,
+/// whose notes are the JSON-encoded `Inbox` of that user.
+pub const NOTIFICATIONS_GROUP: &str = "notifications";
+
+/// Oldest notifications are dropped beyond this many per user.
+const MAX_NOTIFICATIONS_PER_USER: usize = 200;
+
+/// Only this many mentions of a post notify anyone; the rest are ignored.
+const MAX_MENTIONS_PER_POST: usize = 10;
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Stored notification state of one user.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Inbox {
+    /// Threads the user gets reply notifications for.
+    #[serde(default)]
+    pub watching: BTreeSet<String>,
+    /// Notifications, newest first.
+    #[serde(default)]
+    pub notifications: Vec<NotificationDto>,
+}
+
+/// Whether `author` names a user that can receive notifications.
+fn is_known_user(author: &str) -> bool {
+    let author = author.trim();
+    !author.is_empty() && author != "Anonymous"
+}
+
+/// Whether `user` can be mentioned: they wrote a post before or already
+/// have an inbox. Anyone else is just a word after an `@`, and mentioning
+/// it must not create an inbox for it.
+fn is_mentionable(db: &Database, user: &str) -> bool {
+    fn has_posted(group: &Group, user: &str) -> bool {
+        group.children.iter().any(|node| match node.as_ref() {
+            NodeRef::Entry(e) => e.get_username() == Some(user),
+            NodeRef::Group(g) => has_posted(g, user),
+        })
+    }
+
+    is_known_user(user)
+        && (system_record(db, NOTIFICATIONS_GROUP, user).is_some()
+            || categories(&db.root).any(|c| has_posted(c, user)))
+}
+
+/// Extract the distinct `@username` mentions of a post body, in order.
+///
+/// A mention starts with `@` at the beginning of the body or after a
+/// non-alphanumeric character (so e-mail addresses are not mentions) and
+/// runs over letters, digits, `_`, `-` and `.`, minus trailing punctuation.
+pub fn parse_mentions(body: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let at_boundary = prev.is_none_or(|p| !p.is_alphanumeric() && p != '_');
+        prev = Some(c);
+        if c != '@' || !at_boundary {
+            continue;
+        }
+
+        let start = i + 1;
+        let mut end = start;
+        while let Some(&(j, n)) = chars.peek() {
+            if n.is_alphanumeric() || matches!(n, '_' | '-' | '.') {
+                end = j + n.len_utf8();
+                prev = Some(n);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        let name = body[start..end].trim_end_matches(['.', '-']);
+        if !name.is_empty() && !mentions.iter().any(|m| m == name) {
+            mentions.push(name.to_string());
+        }
+    }
+
+    mentions
+}
+
+/// Load the inbox of `user`. Unknown users have an empty one.
+pub fn load_inbox(db: &Database, user: &str) -> Inbox {
+    system_record(db, NOTIFICATIONS_GROUP, user)
+        .and_then(|notes| serde_json::from_str(notes).ok())
+        .unwrap_or_default()
+}
+
+/// Store the inbox of `user`, keeping only the newest notifications.
+pub fn store_inbox(db: &mut Database, user: &str, mut inbox: Inbox) {
+    inbox.notifications.truncate(MAX_NOTIFICATIONS_PER_USER);
+    let notes = serde_json::to_string(&inbox).unwrap_or_default();
+    set_system_record(db, NOTIFICATIONS_GROUP, user, notes);
+}
+
+/// Start or stop watching a thread for `user`.
+pub fn set_watching(
+    db: &mut Database,
+    user: &str,
+    thread_id: &str,
+    watch: bool,
+) -> Result<(), String> {
+    if find_group_by_id(&db.root, thread_id).is_none() {
+        return Err("Thread not found".to_string());
+    }
+    let mut inbox = load_inbox(db, user);
+    if watch {
+        inbox.watching.insert(thread_id.to_string());
+    } else {
+        inbox.watching.remove(thread_id);
+    }
+    store_inbox(db, user, inbox);
+    Ok(())
+}
+
+/// Record the notifications caused by a new post.
+///
+/// Users mentioned in the body get a `mention` notification, up to
+/// `MAX_MENTIONS_PER_POST` of them and only if they are known to the forum
+/// (see `is_mentionable`); other users watching the thread get a `reply`
+/// notification. The author is never
+/// notified about their own post. Whoever starts a thread watches it.
+pub fn record_new_post(
+    db: &mut Database,
+    thread_id: &str,
+    post_id: &str,
+    author: &str,
+    body: &str,
+    starts_thread: bool,
+) {
+    let Some(thread) = find_group_by_id(&db.root, thread_id) else {
+        return;
+    };
+    let thread_title = thread.name.clone();
+    let author = author.trim();
+
+    let mut recipients: Vec<(String, &str)> = parse_mentions(body)
+        .into_iter()
+        .filter(|user| user != author)
+        .take(MAX_MENTIONS_PER_POST)
+        .filter(|user| is_mentionable(db, user))
+        .map(|user| (user, "mention"))
+        .collect();
+    if !starts_thread {
+        let watchers: Vec<String> = system_records(db, NOTIFICATIONS_GROUP)
+            .into_iter()
+            .filter(|(user, notes)| {
+                *user != author
+                    && serde_json::from_str::<Inbox>(notes)
+                        .is_ok_and(|inbox| inbox.watching.contains(thread_id))
+            })
+            .map(|(user, _)| user.to_string())
+            .collect();
+        for user in watchers {
+            if !recipients.iter().any(|(r, _)| *r == user) {
+                recipients.push((user, "reply"));
+            }
+        }
+    }
+
+    let created_at = Times::now().format(TIME_FORMAT).to_string();
+    for (user, kind) in recipients {
+        let mut inbox = load_inbox(db, &user);
+        inbox.notifications.insert(
+            0,
+            NotificationDto {
+                id: Uuid::new_v4().to_string(),
+                kind: kind.to_string(),
+                thread_id: thread_id.to_string(),
+                thread_title: thread_title.clone(),
+                post_id: post_id.to_string(),
+                author: author.to_string(),
+                created_at: created_at.clone(),
+                read: false,
+                message: None,
+            },
+        );
+        store_inbox(db, &user, inbox);
+    }
+
+    if starts_thread && is_known_user(author) {
+        let mut inbox = load_inbox(db, author);
+        inbox.watching.insert(thread_id.to_string());
+        store_inbox(db, author, inbox);
+    }
+}
+
+/// The notifications of `user`, newest first, optionally only the unread ones.
+pub fn notifications_for(db: &Database, user: &str, unread_only: bool) -> NotificationsDto {
+    let inbox = load_inbox(db, user);
+    NotificationsDto {
+        unread: inbox.notifications.iter().filter(|n| !n.read).count(),
+        watching: inbox.watching.into_iter().collect(),
+        notifications: inbox
+            .notifications
+            .into_iter()
+            .filter(|n| !unread_only || !n.read)
+            .collect(),
+    }
+}
+
+/// Mark one notification (or all of them, with `None`) read for `user`.
+pub fn mark_notifications_read(
+    db: &mut Database,
+    user: &str,
+    notification_id: Option<&str>,
+) -> Result<(), String> {
+    let mut inbox = load_inbox(db, user);
+    let mut found = false;
+    for n in &mut inbox.notifications {
+        if notification_id.is_none_or(|id| id == n.id) {
+            n.read = true;
+            found = true;
+        }
+    }
+    if notification_id.is_some() && !found {
+        return Err("Notification not found".to_string());
+    }
+    store_inbox(db, user, inbox);
+    Ok(())
+}
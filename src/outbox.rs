@@ -0,0 +1,312 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{NaiveDateTime, TimeDelta};
+use keepass::{db::Times, Database};
+use lettre::{
+    message::header::ContentType, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        find_entry_by_id, find_group_by_id, remove_system_record,
+        set_system_record, system_records,
+    },
+    events::ForumEvent,
+    state::AppState,
+};
+
+/// System subgroup holding one entry per queued delivery, titled with its id,
+/// whose notes are the JSON-encoded `OutboxItem`.
+pub const OUTBOX_GROUP: &str = "outbox";
+
+/// Deliveries are dropped after this many failed attempts.
+const MAX_ATTEMPTS: u32 = 12;
+/// First retry delay; doubled on every further failure up to `RETRY_MAX`.
+const RETRY_BASE: TimeDelta = TimeDelta::seconds(30);
+const RETRY_MAX: TimeDelta = TimeDelta::hours(1);
+/// How often the worker looks for due deliveries when not woken earlier.
+const WORKER_INTERVAL: Duration = Duration::from_secs(15);
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Where to send notifications about new threads and replies outside the forum.
+#[derive(Clone, Default)]
+pub struct OutboundConfig {
+    /// SMTP relay, as a `smtp://` or `smtps://` URL.
+    pub smtp_url: Option<String>,
+    pub email_from: String,
+    pub email_to: Vec<String>,
+    pub webhook_urls: Vec<String>,
+    /// Leave post bodies out of external messages.
+    pub redact_bodies: bool,
+}
+
+impl OutboundConfig {
+    fn sends_email(&self) -> bool {
+        self.smtp_url.is_some() && !self.email_to.is_empty()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sends_email() || !self.webhook_urls.is_empty()
+    }
+}
+
+/// A message waiting to be sent.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "channel", rename_all = "snake_case")]
+pub enum Delivery {
+    Email {
+        to: Vec<String>,
+        subject: String,
+        text: String,
+    },
+    Webhook {
+        url: String,
+        payload: serde_json::Value,
+    },
+}
+
+/// A queued delivery and its retry state.
+#[derive(Serialize, Deserialize)]
+pub struct OutboxItem {
+    pub id: String,
+    #[serde(flatten)]
+    pub delivery: Delivery,
+    pub attempts: u32,
+    pub next_attempt_at: String,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+fn format_time(time: NaiveDateTime) -> String {
+    time.format(TIME_FORMAT).to_string()
+}
+
+fn store_item(db: &mut Database, item: &OutboxItem) {
+    let notes = serde_json::to_string(item).unwrap_or_default();
+    set_system_record(db, OUTBOX_GROUP, &item.id, notes);
+}
+
+/// Queue external notifications for a new thread or reply; removals send none.
+///
+/// Items are stored in the database, so they are persisted (encrypted)
+/// by the caller's next save and survive restarts.
+pub fn enqueue_post_notifications(db: &mut Database, config: &OutboundConfig, event: &ForumEvent) {
+    if !config.is_enabled() {
+        return;
+    }
+    let new_thread = match event {
+        ForumEvent::ThreadCreated { .. } => true,
+        ForumEvent::ReplyCreated { .. } => false,
+        ForumEvent::ThreadRemoved { .. } | ForumEvent::PostRemoved { .. } => return,
+    };
+    let (Some(thread), Some(post)) = (
+        find_group_by_id(&db.root, event.thread_id()),
+        find_entry_by_id(&db.root, event.post_id()),
+    ) else {
+        return;
+    };
+    let thread_title = thread.name.clone();
+    let author = match post.get_username().unwrap_or("").trim() {
+        "" => "Anonymous".to_string(),
+        name => name.to_string(),
+    };
+    let body = (!config.redact_bodies).then(|| post.get("Notes").unwrap_or("").to_string());
+
+    let mut deliveries = Vec::new();
+    if config.sends_email() {
+        let subject = if new_thread {
+            format!("[kdbx-forum] New thread: {thread_title}")
+        } else {
+            format!("[kdbx-forum] Re: {thread_title}")
+        };
+        let text = match &body {
+            Some(body) => format!("{author} wrote in \"{thread_title}\":\n\n{body}\n"),
+            None => format!(
+                "{author} posted in \"{thread_title}\".\n\n\
+                 Post bodies are not included in external notifications.\n"
+            ),
+        };
+        deliveries.push(Delivery::Email {
+            to: config.email_to.clone(),
+            subject,
+            text,
+        });
+    }
+    if !config.webhook_urls.is_empty() {
+        let mut payload = serde_json::to_value(event).unwrap_or_default();
+        if let Some(fields) = payload.as_object_mut() {
+            fields.insert("thread_title".to_string(), thread_title.clone().into());
+            fields.insert("author".to_string(), author.clone().into());
+            if let Some(body) = &body {
+                fields.insert("body".to_string(), body.clone().into());
+            }
+        }
+        for url in &config.webhook_urls {
+            deliveries.push(Delivery::Webhook {
+                url: url.clone(),
+                payload: payload.clone(),
+            });
+        }
+    }
+
+    let now = format_time(Times::now());
+    for delivery in deliveries {
+        store_item(
+            db,
+            &OutboxItem {
+                id: Uuid::new_v4().to_string(),
+                delivery,
+                attempts: 0,
+                next_attempt_at: now.clone(),
+                last_error: None,
+            },
+        );
+    }
+}
+
+/// Outbox items whose next attempt is due.
+fn due_items(db: &Database) -> Vec<OutboxItem> {
+    let now = format_time(Times::now());
+    system_records(db, OUTBOX_GROUP)
+        .into_iter()
+        .filter_map(|(_, notes)| serde_json::from_str::<OutboxItem>(notes).ok())
+        .filter(|item| item.next_attempt_at <= now)
+        .collect()
+}
+
+/// Sends deliveries over SMTP and HTTP.
+struct Sender {
+    config: Arc<OutboundConfig>,
+    mailer: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    http: reqwest::Client,
+}
+
+impl Sender {
+    fn new(config: Arc<OutboundConfig>) -> Result<Self, String> {
+        let mailer = match &config.smtp_url {
+            Some(url) => Some(
+                AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+                    .map_err(|e| format!("Invalid SMTP URL: {e}"))?
+                    .build(),
+            ),
+            None => None,
+        };
+        Ok(Self {
+            config,
+            mailer,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .map_err(|e| e.to_string())?,
+        })
+    }
+
+    async fn send(&self, delivery: &Delivery) -> Result<(), String> {
+        match delivery {
+            Delivery::Email { to, subject, text } => {
+                let mailer = self
+                    .mailer
+                    .as_ref()
+                    .ok_or_else(|| "No SMTP relay configured".to_string())?;
+                let mut message = Message::builder()
+                    .from(
+                        self.config
+                            .email_from
+                            .parse()
+                            .map_err(|e| format!("Invalid sender address: {e}"))?,
+                    )
+                    .subject(subject)
+                    .header(ContentType::TEXT_PLAIN);
+                for addr in to {
+                    message = message.to(addr
+                        .parse()
+                        .map_err(|e| format!("Invalid recipient '{addr}': {e}"))?);
+                }
+                let message = message.body(text.clone()).map_err(|e| e.to_string())?;
+                mailer.send(message).await.map_err(|e| e.to_string())?;
+            }
+            Delivery::Webhook { url, payload } => {
+                self.http
+                    .post(url)
+                    .json(payload)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Try every due delivery once, then record the outcomes and save.
+async fn deliver_due(state: &AppState, sender: &Sender) {
+    let items = due_items(&*state.db.read().await);
+    if items.is_empty() {
+        return;
+    }
+
+    let mut outcomes = Vec::with_capacity(items.len());
+    for item in items {
+        let result = sender.send(&item.delivery).await;
+        outcomes.push((item, result));
+    }
+
+    let mut db = state.db.write().await;
+    for (mut item, result) in outcomes {
+        match result {
+            Ok(()) => remove_system_record(&mut db, OUTBOX_GROUP, &item.id),
+            Err(e) => {
+                item.attempts += 1;
+                if item.attempts >= MAX_ATTEMPTS {
+                    tracing::error!(
+                        item = %item.id,
+                        attempts = item.attempts,
+                        error = %e,
+                        "Dropping outbox item"
+                    );
+                    remove_system_record(&mut db, OUTBOX_GROUP, &item.id);
+                    continue;
+                }
+                let delay = RETRY_BASE
+                    .checked_mul(1 << (item.attempts - 1).min(16))
+                    .unwrap_or(RETRY_MAX)
+                    .min(RETRY_MAX);
+                tracing::warn!(
+                    item = %item.id,
+                    attempt = item.attempts,
+                    retry_in_secs = delay.num_seconds(),
+                    error = %e,
+                    "Outbox delivery failed"
+                );
+                item.next_attempt_at = format_time(Times::now() + delay);
+                item.last_error = Some(e);
+                store_item(&mut db, &item);
+            }
+        }
+    }
+    if let Err(e) = state.save(&db) {
+        tracing::error!(error = %e, "Failed to save database after outbox delivery");
+    }
+}
+
+/// Deliver queued notifications in the background until the server stops.
+///
+/// The worker checks the outbox every `WORKER_INTERVAL`, and right away
+/// when woken through `AppState::outbox_wake`.
+pub fn spawn_outbox_worker(state: AppState) -> Result<JoinHandle<()>, String> {
+    let sender = Sender::new(state.outbound.clone())?;
+    Ok(tokio::spawn(async move {
+        loop {
+            deliver_due(&state, &sender).await;
+            tokio::select! {
+                _ = state.outbox_wake.notified() => {}
+                _ = tokio::time::sleep(WORKER_INTERVAL) => {}
+            }
+        }
+    }))
+}
@@ -0,0 +1,112 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use keepass::db::{Entry, Value};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::dto::ReactionDto;
+
+/// Custom field holding the reactions on a post as JSON: kind → users.
+pub const REACTIONS_FIELD: &str = "Reactions";
+/// Custom field holding the votes on a post as JSON: user → +1 or -1.
+pub const VOTES_FIELD: &str = "Votes";
+
+/// Supported reaction kinds and the emoji they are shown as.
+pub const REACTION_KINDS: [(&str, &str); 3] =
+    [("thumbs_up", "👍"), ("heart", "❤️"), ("tada", "🎉")];
+
+type Reactions = BTreeMap<String, BTreeSet<String>>;
+type Votes = BTreeMap<String, i8>;
+
+fn read_field<T: DeserializeOwned + Default>(entry: &Entry, field: &str) -> T {
+    entry
+        .get(field)
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+/// Store `value` as JSON in a custom field, or drop the field when empty.
+///
+/// The post's modification time stays as it is: reactions and votes are not
+/// edits, and feeds and imports order posts by that time.
+fn write_field<T: Serialize>(entry: &mut Entry, field: &str, value: &T, is_empty: bool) {
+    if is_empty {
+        entry.fields.remove(field);
+    } else {
+        let json = serde_json::to_string(value).unwrap_or_default();
+        entry
+            .fields
+            .insert(field.to_string(), Value::Unprotected(json));
+    }
+}
+
+/// Add or remove `user`'s reaction of the given kind. Each user reacts at most once per kind.
+pub fn set_reaction(entry: &mut Entry, kind: &str, user: &str, on: bool) -> Result<(), String> {
+    if !REACTION_KINDS.iter().any(|(k, _)| *k == kind) {
+        return Err(format!("Unknown reaction '{kind}'"));
+    }
+
+    let mut reactions: Reactions = read_field(entry, REACTIONS_FIELD);
+    let users = reactions.entry(kind.to_string()).or_default();
+    if on {
+        users.insert(user.to_string());
+    } else {
+        users.remove(user);
+    }
+    reactions.retain(|_, users| !users.is_empty());
+    let is_empty = reactions.is_empty();
+    write_field(entry, REACTIONS_FIELD, &reactions, is_empty);
+    Ok(())
+}
+
+/// Set `user`'s vote on a post: 1 (up), -1 (down) or 0 (withdraw).
+pub fn set_vote(entry: &mut Entry, user: &str, value: i8) -> Result<(), String> {
+    let mut votes: Votes = read_field(entry, VOTES_FIELD);
+    match value {
+        0 => {
+            votes.remove(user);
+        }
+        1 | -1 => {
+            votes.insert(user.to_string(), value);
+        }
+        _ => return Err("Vote must be 1, -1 or 0".to_string()),
+    }
+    let is_empty = votes.is_empty();
+    write_field(entry, VOTES_FIELD, &votes, is_empty);
+    Ok(())
+}
+
+/// Up votes minus down votes.
+pub fn score(entry: &Entry) -> i64 {
+    read_field::<Votes>(entry, VOTES_FIELD)
+        .values()
+        .map(|v| i64::from(*v))
+        .sum()
+}
+
+/// The vote `viewer` cast on a post, 0 if none.
+pub fn vote_of(entry: &Entry, viewer: Option<&str>) -> i8 {
+    let Some(viewer) = viewer else {
+        return 0;
+    };
+    read_field::<Votes>(entry, VOTES_FIELD)
+        .get(viewer)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Counts of every supported reaction on a post, in a fixed order.
+pub fn reaction_dtos(entry: &Entry, viewer: Option<&str>) -> Vec<ReactionDto> {
+    let reactions: Reactions = read_field(entry, REACTIONS_FIELD);
+    REACTION_KINDS
+        .iter()
+        .map(|(kind, emoji)| {
+            let users = reactions.get(*kind);
+            ReactionDto {
+                kind: kind.to_string(),
+                emoji: emoji.to_string(),
+                count: users.map_or(0, BTreeSet::len),
+                reacted_by_me: viewer.is_some_and(|v| users.is_some_and(|u| u.contains(v))),
+            }
+        })
+        .collect()
+}
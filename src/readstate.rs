@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use keepass::{
+    db::{Entry, Group, NodeRef, Times},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{categories, find_group_by_id, set_system_record, system_record},
+    dto::{CategoryUnreadDto, UnreadDto, UnreadThreadDto},
+    moderation::is_published,
+};
+
+/// System subgroup holding one entry per user, titled with the user name,
+/// whose notes are the JSON-encoded read markers of that user.
+pub const READ_STATE_GROUP: &str = "read-state";
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// How far a user has read a thread: the newest post seen, and its creation time.
+///
+/// Posts created after `read_at` are unread. Comparing times rather than
+/// post counts keeps markers valid when posts are deleted or moved.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReadMarker {
+    pub post_id: String,
+    pub read_at: String,
+}
+
+/// Read markers of one user, keyed by thread id.
+pub type ReadMarkers = BTreeMap<String, ReadMarker>;
+
+fn creation_time(entry: &Entry) -> NaiveDateTime {
+    entry
+        .times
+        .get_creation()
+        .copied()
+        .unwrap_or_else(Times::epoch)
+}
+
+fn marker_time(marker: &ReadMarker) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(&marker.read_at, TIME_FORMAT).unwrap_or_else(|_| Times::epoch())
+}
+
+/// Load the read markers of `user`. Unknown users have read nothing.
+pub fn load_markers(db: &Database, user: &str) -> ReadMarkers {
+    system_record(db, READ_STATE_GROUP, user)
+        .and_then(|notes| serde_json::from_str(notes).ok())
+        .unwrap_or_default()
+}
+
+/// Store the read markers of `user`, dropping markers of threads that no longer exist.
+pub fn store_markers(db: &mut Database, user: &str, mut markers: ReadMarkers) {
+    markers.retain(|thread_id, _| find_group_by_id(&db.root, thread_id).is_some());
+    let notes = serde_json::to_string(&markers).unwrap_or_default();
+    set_system_record(db, READ_STATE_GROUP, user, notes);
+}
+
+/// Marker for having read every post currently in `thread`, if it has any.
+fn marker_for_thread(thread: &Group) -> Option<ReadMarker> {
+    thread
+        .entries()
+        .into_iter()
+        .filter(|e| is_published(e))
+        .max_by_key(|e| creation_time(e))
+        .map(|newest| ReadMarker {
+            post_id: newest.uuid.to_string(),
+            read_at: creation_time(newest).format(TIME_FORMAT).to_string(),
+        })
+}
+
+/// Number of posts in `thread` created after the marker (all of them without one).
+pub fn unread_posts(thread: &Group, marker: Option<&ReadMarker>) -> usize {
+    let entries: Vec<&Entry> = thread
+        .entries()
+        .into_iter()
+        .filter(|e| is_published(e))
+        .collect();
+    match marker {
+        Some(marker) => {
+            let read_at = marker_time(marker);
+            entries
+                .into_iter()
+                .filter(|e| creation_time(e) > read_at)
+                .count()
+        }
+        None => entries.len(),
+    }
+}
+
+/// Mark every post in a thread as read for `user`.
+///
+/// Returns whether anything changed, so callers can skip saving.
+pub fn mark_thread_read(db: &mut Database, user: &str, thread_id: &str) -> Result<bool, String> {
+    let thread =
+        find_group_by_id(&db.root, thread_id).ok_or_else(|| "Thread not found".to_string())?;
+    let Some(marker) = marker_for_thread(thread) else {
+        return Ok(false);
+    };
+
+    let mut markers = load_markers(db, user);
+    if markers
+        .get(thread_id)
+        .is_some_and(|old| old.post_id == marker.post_id && old.read_at == marker.read_at)
+    {
+        return Ok(false);
+    }
+    markers.insert(thread_id.to_string(), marker);
+    store_markers(db, user, markers);
+    Ok(true)
+}
+
+/// Mark every thread in a category as read for `user`. Returns the number of threads marked.
+pub fn mark_category_read(
+    db: &mut Database,
+    user: &str,
+    category_id: &str,
+) -> Result<usize, String> {
+    let category =
+        find_group_by_id(&db.root, category_id).ok_or_else(|| "Category not found".to_string())?;
+
+    let mut markers = load_markers(db, user);
+    let mut count = 0;
+    for node in &category.children {
+        if let NodeRef::Group(thread) = node.as_ref()
+            && let Some(marker) = marker_for_thread(thread)
+        {
+            markers.insert(thread.uuid.to_string(), marker);
+            count += 1;
+        }
+    }
+    store_markers(db, user, markers);
+    Ok(count)
+}
+
+/// Unread counts per category, and every thread with unread posts, for `user`.
+pub fn unread_summary(db: &Database, user: &str) -> UnreadDto {
+    let markers = load_markers(db, user);
+    let mut out = UnreadDto {
+        categories: Vec::new(),
+        threads: Vec::new(),
+    };
+
+    for category in categories(&db.root) {
+        let mut unread_threads = 0;
+        let mut unread_post_total = 0;
+        for node in &category.children {
+            let NodeRef::Group(thread) = node.as_ref() else {
+                continue;
+            };
+            let id = thread.uuid.to_string();
+            let unread = unread_posts(thread, markers.get(&id));
+            if unread == 0 {
+                continue;
+            }
+            unread_threads += 1;
+            unread_post_total += unread;
+            out.threads.push(UnreadThreadDto {
+                id,
+                category_id: category.uuid.to_string(),
+                title: thread.name.clone(),
+                unread_posts: unread,
+            });
+        }
+        out.categories.push(CategoryUnreadDto {
+            id: category.uuid.to_string(),
+            name: category.name.clone(),
+            unread_threads,
+            unread_posts: unread_post_total,
+        });
+    }
+
+    out
+}
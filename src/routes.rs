@@ -1,23 +1,65 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    async_trait,
+    extract::{ConnectInfo, FromRequest, Multipart, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::Next,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
     Json,
 };
-use keepass::db::NodeRef;
-use serde::Deserialize;
+use futures_util::stream::{self, Stream};
+use keepass::DatabaseKey;
+use percent_encoding::percent_decode_str;
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, warn};
 
 use crate::{
+    attachments::{attachment_usage_in_group, get_attachment, AttachmentLimits, NewAttachment},
+    audit::{audit_log, record_event, verify_audit_log, Actor},
+    events::ForumEvent,
+    logging::redact,
+    metrics::render_gauge,
+    export::{build_export_database, database_to_bytes, find_category_of},
+    feed::{collect_category_items, most_recent, render_atom, DEFAULT_FEED_LIMIT},
     db::{
-        add_reply_to_thread, add_thread_to_category, count_entries_in_group, entry_to_post_dto,
-        find_group_by_id, save_database,
+        add_reply_to_thread, add_thread_to_category, categories, category_dtos,
+        count_entries_in_group, find_entry_by_id, find_entry_by_id_mut, find_group_by_id,
+        find_group_by_id_mut, find_thread_by_id, open_database, thread_detail, thread_summaries,
+    },
+    dto::{
+        AttachmentUsageDto, CategoryAttachmentUsageDto, ModerationOverviewDto, ReadinessDto,
     },
-    dto::{CategoryDto, ThreadDetailDto, ThreadSummaryDto},
+    integrity::check_integrity,
+    moderation::{
+        can_see, dismiss_report, file_report, is_banned, is_category_locked, is_thread_locked,
+        load_settings, moderate_post, moderate_user, pending_posts, post_lock_reason,
+        post_visible_to, reports, requires_approval, set_category_approval, set_category_locked,
+        set_thread_locked, thread_lock_reason, PostAction, UserAction,
+    },
+    notifications::{mark_notifications_read, notifications_for, set_watching},
+    outbox::enqueue_post_notifications,
+    reactions::{set_reaction, set_vote},
+    tags::{
+        deserialize_tags, matches_tags, parse_tag_list, set_thread_tags, tag_counts,
+        threads_with_tag,
+    },
+    readstate::{self, load_markers, unread_posts, unread_summary},
     state::AppState,
 };
 
-/// Forum frontend page (HTML + JS).
-pub async fn index() -> Html<String> {
+/// Forum frontend page (HTML + JS). A read-only instance gets the page
+/// without composers or any other control that would write; a forum mounted
+/// under `/f/<name>` gets its API calls prefixed with that path.
+pub async fn index(State(state): State<AppState>) -> Html<String> {
     let body = r#"<!doctype html>
 <html lang="en">
 <head>
@@ -41,24 +83,62 @@ pub async fn index() -> Html<String> {
     .muted { color: #666; font-size: 0.9rem; }
     .thread-unread { font-weight: 600; }
     .thread-read { font-weight: 400; }
+    .post-attachments { margin-top: 0.25rem; }
+    .post-attachments a { display: inline-block; margin-right: 0.5rem; }
+    .thumb { max-width: 160px; max-height: 120px; border: 1px solid #ddd; }
+    .badge { background: #d73a49; color: #fff; border-radius: 0.6rem; padding: 0 0.4rem; font-size: 0.8rem; }
+    .notification-unread { font-weight: 600; }
+    .post-actions { margin-top: 0.25rem; }
+    .post-actions button { margin-right: 0.25rem; background: #fff; border: 1px solid #ddd; border-radius: 0.8rem; cursor: pointer; }
+    .post-actions button.active { background: #ddf4ff; border-color: #54aeff; }
+    .tag { background: #eef; border-radius: 0.6rem; padding: 0 0.4rem; margin-left: 0.25rem; font-size: 0.8rem; cursor: pointer; }
+    .post-status { color: #b08800; font-size: 0.85rem; }
+    .best-answer { background: #f0fff4; border-left: 3px solid #2ea44f; padding-left: 0.5rem; }
   </style>
 </head>
 <body>
   <div id="sidebar">
     <h2>kdbx-forum</h2>
     <p class="muted">Mini forum backed by a KeePass KDBX file.</p>
+    <p class="muted" id="all-forums" style="display:none;"><a href="/">All forums</a></p>
+    <p class="muted" id="read-only-note" style="display:none;">This is a read-only copy: posting is switched off.</p>
 
     <h3>Your name</h3>
     <input type="text" id="username" placeholder="Anonymous" />
     <p class="muted">Used as the author when you post threads or replies.</p>
 
+    <h3>Notifications <span id="notification-badge" class="badge" style="display:none;"></span></h3>
+    <ul id="notifications"></ul>
+    <button id="notifications-read-all" style="display:none;">Mark all read</button>
+
+    <div id="moderation-section" style="display:none;">
+      <h3>Moderation</h3>
+      <p class="muted" id="moderation-summary"></p>
+      <ul id="moderation-items"></ul>
+    </div>
+
     <h3>Categories</h3>
     <ul id="categories"></ul>
+
+    <h3>Tags</h3>
+    <ul id="tags"></ul>
   </div>
 
   <div id="main">
     <section>
       <h2 id="current-category-title">Select a category</h2>
+      <button id="mark-category-read" style="display:none;">Mark all read</button>
+      <label id="require-approval-label" style="display:none;">
+        <input type="checkbox" id="require-approval" /> Require approval
+      </label>
+      <label id="lock-category-label" style="display:none;">
+        <input type="checkbox" id="lock-category" /> Locked
+      </label>
+      <span id="category-locked" class="muted" style="display:none;">🔒 Locked</span>
+      <select id="thread-sort">
+        <option value="">Sort: original order</option>
+        <option value="score">Sort: highest score</option>
+      </select>
       <ul id="threads"></ul>
     </section>
 
@@ -68,18 +148,29 @@ pub async fn index() -> Html<String> {
       <br><br>
       <textarea id="new-thread-body" placeholder="Thread body"></textarea>
       <br>
+      <input type="text" id="new-thread-tags" placeholder="Tags, comma-separated" />
+      <br>
+      <input type="file" id="new-thread-files" multiple />
+      <br>
       <button id="new-thread-submit">Post thread</button>
       <span id="new-thread-status" class="muted"></span>
     </section>
 
     <section style="margin-top:2rem;">
       <h2 id="current-thread-title">Thread</h2>
+      <button id="watch-thread" style="display:none;">Watch</button>
+      <button id="edit-tags" style="display:none;">Edit tags</button>
+      <button id="lock-thread" style="display:none;">Lock</button>
+      <span id="thread-locked" class="muted" style="display:none;">🔒 Locked</span>
+      <div id="thread-tags"></div>
       <div id="thread-posts"></div>
 
       <div id="reply-section" style="display:none; margin-top:1rem;">
         <h3>Reply</h3>
         <textarea id="reply-body" placeholder="Write your reply here"></textarea>
         <br>
+        <input type="file" id="reply-files" multiple />
+        <br>
         <button id="reply-submit">Post reply</button>
         <span id="reply-status" class="muted"></span>
       </div>
@@ -89,46 +180,236 @@ pub async fn index() -> Html<String> {
   <script>
     let selectedCategoryId = null;
     let selectedThreadId = null;
+    let currentThreadTags = [];
+    let watchingThreads = new Set();
+    let isModerator = false;
+    let approvalCategories = new Set();
+    let currentThreadLocked = false;
+    let selectedCategoryLocked = false;
+    let currentCategoryLocked = false;
+    // Set by the server: a read-only instance has no write routes, so every
+    // control that would write is hidden.
+    const readOnly = __READ_ONLY__;
+    // Where this forum is mounted: empty at the root, `/f/<name>` when the
+    // server hosts several forums.
+    const base = __BASE__;
 
-    function loadReadState() {
-      try {
-        const raw = localStorage.getItem('kdbx_forum_read_state');
-        if (!raw) return {};
-        const parsed = JSON.parse(raw);
-        return (parsed && typeof parsed === 'object') ? parsed : {};
-      } catch (e) {
-        console.warn('Failed to parse read state from localStorage', e);
-        return {};
-      }
+    // Read state lives on the server, per user; anonymous visitors get none.
+    function userHeaders() {
+      const name = getUsername();
+      return name === 'Anonymous' ? {} : { 'X-Forum-User': encodeURIComponent(name) };
     }
 
-    function saveReadState(state) {
-      try {
-        localStorage.setItem('kdbx_forum_read_state', JSON.stringify(state));
-      } catch (e) {
-        console.warn('Failed to save read state to localStorage', e);
-      }
+    async function markThreadRead(threadId) {
+      if (readOnly || getUsername() === 'Anonymous') return;
+      await fetch(base + '/threads/' + encodeURIComponent(threadId) + '/read', {
+        method: 'POST',
+        headers: userHeaders()
+      });
+      setThreadUnread(threadId, 0);
+      await loadUnreadCounts();
     }
 
-    function markThreadRead(threadId, postCount) {
-      const state = loadReadState();
-      state[threadId] = postCount;
-      saveReadState(state);
+    function setThreadUnread(threadId, unread) {
+      document.querySelectorAll('#threads a[data-thread-id]').forEach(a => {
+        if (a.dataset.threadId === threadId) a.dataset.unread = String(unread);
+      });
       updateThreadListReadStyles();
     }
 
     function updateThreadListReadStyles() {
-      const state = loadReadState();
       document.querySelectorAll('#threads a[data-thread-id]').forEach(a => {
-        const id = a.dataset.threadId;
-        const postCount = Number(a.dataset.postCount || '0');
-        const lastSeen = state[id] || 0;
-        const isRead = lastSeen >= postCount && postCount > 0;
+        const isRead = Number(a.dataset.unread || '0') === 0;
         a.classList.toggle('thread-read', isRead);
         a.classList.toggle('thread-unread', !isRead);
       });
     }
 
+    async function loadUnreadCounts() {
+      document.querySelectorAll('#categories .unread-count').forEach(s => s.textContent = '');
+      if (getUsername() === 'Anonymous') return;
+      const res = await fetch(base + '/me/unread', { headers: userHeaders() });
+      if (!res.ok) return;
+      const unread = await res.json();
+      unread.categories.forEach(cat => {
+        const span = document.querySelector('#categories .unread-count[data-category-id="' + cat.id + '"]');
+        if (span && cat.unread_threads > 0) span.textContent = ' (' + cat.unread_threads + ' unread)';
+      });
+    }
+
+    async function loadNotifications() {
+      const ul = document.getElementById('notifications');
+      const badge = document.getElementById('notification-badge');
+      const readAll = document.getElementById('notifications-read-all');
+      ul.innerHTML = '';
+      badge.style.display = 'none';
+      readAll.style.display = 'none';
+      watchingThreads = new Set();
+      if (getUsername() === 'Anonymous') {
+        updateWatchButton();
+        return;
+      }
+      const res = await fetch(base + '/notifications', { headers: userHeaders() });
+      if (!res.ok) return;
+      const inbox = await res.json();
+      watchingThreads = new Set(inbox.watching);
+      updateWatchButton();
+      if (inbox.unread > 0) {
+        badge.textContent = String(inbox.unread);
+        badge.style.display = 'inline';
+        readAll.style.display = readOnly ? 'none' : 'inline-block';
+      }
+      inbox.notifications.slice(0, 10).forEach(n => {
+        const li = document.createElement('li');
+        const a = document.createElement('a');
+        a.className = n.read ? '' : 'notification-unread';
+        if (n.kind === 'warning') {
+          a.textContent = 'Warning from ' + n.author + (n.message ? ': ' + n.message : '');
+        } else {
+          a.textContent = (n.kind === 'mention' ? n.author + ' mentioned you in ' : n.author + ' replied to ') +
+            n.thread_title;
+        }
+        a.onclick = async () => {
+          if (!n.read && !readOnly) {
+            await fetch(base + '/notifications/' + encodeURIComponent(n.id) + '/read', {
+              method: 'POST',
+              headers: userHeaders()
+            });
+          }
+          if (n.thread_id) await selectThread({ id: n.thread_id, title: n.thread_title });
+          await loadNotifications();
+        };
+        li.appendChild(a);
+        ul.appendChild(li);
+      });
+    }
+
+    function moderationRequest(url, method, payload) {
+      const headers = userHeaders();
+      const init = { method, headers };
+      if (payload !== undefined) {
+        headers['Content-Type'] = 'application/json';
+        init.body = JSON.stringify(payload);
+      }
+      return fetch(url, init);
+    }
+
+    async function loadModeration() {
+      const section = document.getElementById('moderation-section');
+      isModerator = false;
+      approvalCategories = new Set();
+      section.style.display = 'none';
+      if (getUsername() !== 'Anonymous') {
+        const res = await fetch(base + '/moderation/settings', { headers: userHeaders() });
+        if (res.ok) {
+          const settings = await res.json();
+          isModerator = true;
+          approvalCategories = new Set(settings.approval_categories);
+        }
+      }
+      updateModerationControls();
+      if (!isModerator) return;
+      section.style.display = 'block';
+      const [queueRes, reportsRes] = await Promise.all([
+        fetch(base + '/moderation/queue', { headers: userHeaders() }),
+        fetch(base + '/moderation/reports', { headers: userHeaders() })
+      ]);
+      const queue = queueRes.ok ? await queueRes.json() : [];
+      const reports = reportsRes.ok ? await reportsRes.json() : [];
+      document.getElementById('moderation-summary').textContent =
+        queue.length + ' awaiting approval, ' + reports.length + ' open reports';
+      const ul = document.getElementById('moderation-items');
+      ul.innerHTML = '';
+      queue.forEach(q => {
+        const li = document.createElement('li');
+        const a = document.createElement('a');
+        a.textContent = 'Approve? ' + (q.post.author || 'Anonymous') + ' in ' + q.thread_title;
+        a.onclick = () => selectThread({ id: q.thread_id, title: q.thread_title });
+        li.appendChild(a);
+        ul.appendChild(li);
+      });
+      reports.forEach(r => {
+        const li = document.createElement('li');
+        const a = document.createElement('a');
+        a.textContent = 'Report by ' + r.reporter + ' in ' + r.thread_title + (r.reason ? ': ' + r.reason : '');
+        a.onclick = () => selectThread({ id: r.thread_id, title: r.thread_title });
+        const dismiss = document.createElement('button');
+        dismiss.textContent = 'Dismiss';
+        dismiss.onclick = async () => {
+          await moderationRequest(base + '/moderation/reports/' + encodeURIComponent(r.id) + '/dismiss', 'POST');
+          await loadModeration();
+        };
+        li.appendChild(a);
+        li.appendChild(dismiss);
+        ul.appendChild(li);
+      });
+    }
+
+    function updateModerationControls() {
+      const canModerate = isModerator && !readOnly;
+      const approvalLabel = document.getElementById('require-approval-label');
+      approvalLabel.style.display = canModerate && selectedCategoryId ? 'inline' : 'none';
+      document.getElementById('require-approval').checked = approvalCategories.has(selectedCategoryId);
+      document.getElementById('lock-category-label').style.display = canModerate && selectedCategoryId ? 'inline' : 'none';
+      document.getElementById('lock-category').checked = selectedCategoryLocked;
+      document.getElementById('category-locked').style.display =
+        !canModerate && selectedCategoryId && selectedCategoryLocked ? 'inline' : 'none';
+      document.getElementById('new-thread-section').style.display =
+        selectedCategoryId && !readOnly && !selectedCategoryLocked ? 'block' : 'none';
+      document.getElementById('lock-thread').style.display = canModerate && selectedThreadId ? 'inline-block' : 'none';
+      document.getElementById('lock-thread').textContent = currentThreadLocked ? 'Unlock' : 'Lock';
+      document.getElementById('thread-locked').style.display =
+        selectedThreadId && (currentThreadLocked || currentCategoryLocked) ? 'inline' : 'none';
+      document.getElementById('reply-section').style.display =
+        selectedThreadId && !readOnly && !currentThreadLocked && !currentCategoryLocked ? 'block' : 'none';
+    }
+
+    document.getElementById('require-approval').addEventListener('change', async (ev) => {
+      if (!selectedCategoryId) return;
+      await moderationRequest(base + '/moderation/categories/' + encodeURIComponent(selectedCategoryId), 'PUT',
+        { require_approval: ev.target.checked });
+      await loadModeration();
+    });
+
+    document.getElementById('lock-category').addEventListener('change', async (ev) => {
+      if (!selectedCategoryId) return;
+      const res = await moderationRequest(base + '/moderation/categories/' + encodeURIComponent(selectedCategoryId), 'PUT',
+        { locked: ev.target.checked });
+      if (res.ok) selectedCategoryLocked = ev.target.checked;
+      updateModerationControls();
+    });
+
+    document.getElementById('lock-thread').addEventListener('click', async () => {
+      if (!selectedThreadId) return;
+      await moderationRequest(base + '/moderation/threads/' + encodeURIComponent(selectedThreadId) + '/lock',
+        currentThreadLocked ? 'DELETE' : 'POST');
+      await loadThreadDetail(selectedThreadId);
+    });
+
+    function updateWatchButton() {
+      const button = document.getElementById('watch-thread');
+      if (readOnly || !selectedThreadId || getUsername() === 'Anonymous') {
+        button.style.display = 'none';
+        return;
+      }
+      button.style.display = 'inline-block';
+      button.textContent = watchingThreads.has(selectedThreadId) ? 'Unwatch' : 'Watch';
+    }
+
+    document.getElementById('watch-thread').addEventListener('click', async () => {
+      if (!selectedThreadId) return;
+      await fetch(base + '/threads/' + encodeURIComponent(selectedThreadId) + '/watch', {
+        method: watchingThreads.has(selectedThreadId) ? 'DELETE' : 'POST',
+        headers: userHeaders()
+      });
+      await loadNotifications();
+    });
+
+    document.getElementById('notifications-read-all').addEventListener('click', async () => {
+      await fetch(base + '/notifications/read', { method: 'POST', headers: userHeaders() });
+      await loadNotifications();
+    });
+
     function getUsername() {
       const stored = localStorage.getItem('kdbx_forum_username') || '';
       const field = document.getElementById('username');
@@ -142,8 +423,15 @@ pub async fn index() -> Html<String> {
       localStorage.setItem('kdbx_forum_username', e.target.value);
     });
 
+    document.getElementById('username').addEventListener('change', () => {
+      loadModeration().catch(console.error);
+      loadUnreadCounts().catch(console.error);
+      loadNotifications().catch(console.error);
+      if (selectedCategoryId) loadThreads(selectedCategoryId).catch(console.error);
+    });
+
     async function loadCategories() {
-      const res = await fetch('/categories');
+      const res = await fetch(base + '/categories');
       if (!res.ok) {
         console.error('Failed to load categories:', res.status);
         return;
@@ -164,26 +452,101 @@ pub async fn index() -> Html<String> {
         const a = document.createElement('a');
         a.textContent = cat.name || '(no name)';
         a.onclick = function () { selectCategory(cat); };
+        const count = document.createElement('span');
+        count.className = 'unread-count muted';
+        count.dataset.categoryId = cat.id;
+        li.appendChild(a);
+        li.appendChild(count);
+        ul.appendChild(li);
+      }
+      await loadUnreadCounts();
+    }
+
+    function renderTags(tags) {
+      const wrap = document.createElement('span');
+      (tags || []).forEach(tag => {
+        const span = document.createElement('span');
+        span.className = 'tag';
+        span.textContent = tag;
+        span.onclick = (ev) => { ev.stopPropagation(); showTag(tag).catch(console.error); };
+        wrap.appendChild(span);
+      });
+      return wrap;
+    }
+
+    async function loadTags() {
+      const res = await fetch(base + '/tags');
+      if (!res.ok) {
+        console.error('Failed to load tags:', res.status);
+        return;
+      }
+      const tags = await res.json();
+      const ul = document.getElementById('tags');
+      ul.innerHTML = '';
+      tags.forEach(t => {
+        const li = document.createElement('li');
+        const a = document.createElement('a');
+        a.textContent = t.tag + ' (' + t.count + ')';
+        a.onclick = () => showTag(t.tag).catch(console.error);
         li.appendChild(a);
         ul.appendChild(li);
+      });
+    }
+
+    async function showTag(tag) {
+      const res = await fetch(base + '/tags/' + encodeURIComponent(tag) + '/threads');
+      if (!res.ok) {
+        console.error('Failed to load tagged threads:', res.status);
+        return;
       }
+      const threads = await res.json();
+      selectedCategoryId = null;
+      document.getElementById('current-category-title').textContent = 'Tag: ' + tag;
+      document.getElementById('new-thread-section').style.display = 'none';
+      document.getElementById('mark-category-read').style.display = 'none';
+      const ul = document.getElementById('threads');
+      ul.innerHTML = '';
+      threads.forEach(th => {
+        const li = document.createElement('li');
+        const a = document.createElement('a');
+        a.dataset.threadId = th.id;
+        a.dataset.postCount = String(th.post_count || 0);
+        a.textContent = th.title + ' (' + th.post_count + ' posts) ';
+        a.onclick = () => selectThread(th);
+        li.appendChild(a);
+        li.appendChild(document.createTextNode(' in ' + th.category_name));
+        li.appendChild(renderTags(th.tags));
+        ul.appendChild(li);
+      });
+      updateThreadListReadStyles();
     }
 
     async function selectCategory(cat) {
       selectedCategoryId = cat.id;
+      selectedCategoryLocked = !!cat.locked;
       selectedThreadId = null;
       document.getElementById('current-category-title').textContent = 'Category: ' + cat.name;
       document.getElementById('threads').innerHTML = '';
       document.getElementById('thread-posts').innerHTML = '';
       document.getElementById('current-thread-title').textContent = 'Thread';
       document.getElementById('reply-section').style.display = 'none';
-      document.getElementById('new-thread-section').style.display = 'block';
+      document.getElementById('thread-tags').innerHTML = '';
+      document.getElementById('edit-tags').style.display = 'none';
+      updateWatchButton();
+      document.getElementById('mark-category-read').style.display =
+        readOnly || getUsername() === 'Anonymous' ? 'none' : 'inline-block';
       document.getElementById('new-thread-status').textContent = '';
+      currentThreadLocked = false;
+      updateModerationControls();
       await loadThreads(cat.id);
     }
 
     async function loadThreads(categoryId) {
-      const res = await fetch('/categories/' + encodeURIComponent(categoryId) + '/threads');
+      const sort = document.getElementById('thread-sort').value;
+      const res = await fetch(base + '/categories/' + encodeURIComponent(categoryId) + '/threads' +
+        (sort ? '?sort=' + sort : ''), {
+        headers: userHeaders()
+      });
       if (!res.ok) {
         console.error('Failed to load threads:', res.status);
         return;
@@ -197,18 +560,17 @@ pub async fn index() -> Html<String> {
         ul.appendChild(li);
         return;
       }
-      const readState = loadReadState();
       threads.forEach(th => {
         const li = document.createElement('li');
         const a = document.createElement('a');
         a.dataset.threadId = th.id;
         a.dataset.postCount = String(th.post_count || 0);
-        const lastSeen = readState[th.id] || 0;
-        const isRead = lastSeen >= th.post_count && th.post_count > 0;
-        a.className = isRead ? 'thread-read' : 'thread-unread';
-        a.textContent = th.title + ' (' + th.post_count + ' posts)';
+        a.dataset.unread = String(th.unread_posts || 0);
+        a.textContent = (th.score ? '[' + (th.score > 0 ? '+' : '') + th.score + '] ' : '') +
+          th.title + ' (' + th.post_count + ' posts)';
         a.onclick = () => selectThread(th);
         li.appendChild(a);
+        li.appendChild(renderTags(th.tags));
         ul.appendChild(li);
       });
       updateThreadListReadStyles();
@@ -217,36 +579,210 @@ pub async fn index() -> Html<String> {
     async function selectThread(th) {
       selectedThreadId = th.id;
       document.getElementById('current-thread-title').textContent = 'Thread: ' + th.title;
-      document.getElementById('reply-section').style.display = 'block';
       document.getElementById('reply-status').textContent = '';
+      updateWatchButton();
       await loadThreadDetail(th.id);
     }
 
+    function attachmentUrl(postId, name) {
+      return base + '/posts/' + encodeURIComponent(postId) + '/attachments/' + encodeURIComponent(name);
+    }
+
+    function renderAttachments(post) {
+      const wrap = document.createElement('div');
+      wrap.className = 'post-attachments';
+      post.attachments.forEach(att => {
+        const a = document.createElement('a');
+        a.href = attachmentUrl(post.id, att.name);
+        a.target = '_blank';
+        if (att.content_type.startsWith('image/')) {
+          const img = document.createElement('img');
+          img.className = 'thumb';
+          img.src = a.href;
+          img.alt = att.name;
+          a.appendChild(img);
+        } else {
+          a.textContent = att.name + ' (' + Math.ceil(att.size / 1024) + ' KiB)';
+        }
+        wrap.appendChild(a);
+      });
+      return wrap;
+    }
+
+    function buildPostForm(fields, fileInput) {
+      const form = new FormData();
+      Object.entries(fields).forEach(([k, v]) => form.append(k, v));
+      Array.from(fileInput.files || []).forEach(f => form.append('attachment', f, f.name));
+      return form;
+    }
+
+    async function postAction(url, method, payload) {
+      if (getUsername() === 'Anonymous') {
+        alert('Enter your name first to react or vote.');
+        return;
+      }
+      const headers = userHeaders();
+      const init = { method, headers };
+      if (payload !== undefined) {
+        headers['Content-Type'] = 'application/json';
+        init.body = JSON.stringify(payload);
+      }
+      const res = await fetch(url, init);
+      if (!res.ok) console.error('Action failed:', res.status);
+      if (selectedThreadId) await loadThreadDetail(selectedThreadId);
+    }
+
+    function renderPostActions(post) {
+      const wrap = document.createElement('div');
+      wrap.className = 'post-actions';
+      if (readOnly) {
+        wrap.className = 'post-actions muted';
+        wrap.textContent = 'Score ' + post.score +
+          post.reactions.filter(r => r.count).map(r => '  ' + r.emoji + ' ' + r.count).join('');
+        return wrap;
+      }
+      const postUrl = base + '/posts/' + encodeURIComponent(post.id);
+      [[1, '▲'], [-1, '▼']].forEach(([value, label]) => {
+        const b = document.createElement('button');
+        b.textContent = label;
+        b.className = post.my_vote === value ? 'active' : '';
+        b.onclick = () => postAction(postUrl + '/vote', 'POST', { value: post.my_vote === value ? 0 : value });
+        wrap.appendChild(b);
+        if (value === 1) {
+          const score = document.createElement('span');
+          score.textContent = ' ' + post.score + ' ';
+          wrap.appendChild(score);
+        }
+      });
+      post.reactions.forEach(r => {
+        const b = document.createElement('button');
+        b.textContent = r.emoji + (r.count ? ' ' + r.count : '');
+        b.className = r.reacted_by_me ? 'active' : '';
+        b.onclick = () => postAction(postUrl + '/reactions/' + r.kind, r.reacted_by_me ? 'DELETE' : 'POST');
+        wrap.appendChild(b);
+      });
+      const report = document.createElement('button');
+      report.textContent = '⚑ Report';
+      report.onclick = () => {
+        if (getUsername() === 'Anonymous') {
+          alert('Enter your name first to report a post.');
+          return;
+        }
+        const reason = prompt('Why should moderators look at this post?');
+        if (reason === null) return;
+        postAction(postUrl + '/report', 'POST', { reason });
+      };
+      wrap.appendChild(report);
+      if (isModerator) {
+        const actions = post.status === 'pending' ? ['approve', 'delete']
+          : post.status === 'hidden' ? ['unhide', 'delete'] : ['hide', 'delete'];
+        actions.forEach(action => {
+          const b = document.createElement('button');
+          b.textContent = action;
+          b.onclick = async () => {
+            if (action === 'delete' && !confirm('Delete this post for good?')) return;
+            const res = await moderationRequest(base + '/moderation/posts/' + encodeURIComponent(post.id) + '/' + action, 'POST');
+            if (!res.ok) alert('Failed: ' + await res.text());
+            await loadModeration();
+            if (selectedCategoryId) await loadThreads(selectedCategoryId);
+            if (selectedThreadId) await loadThreadDetail(selectedThreadId);
+          };
+          wrap.appendChild(b);
+        });
+        const author = post.author || '';
+        if (author && author !== 'Anonymous') {
+          ['warn', 'ban'].forEach(action => {
+            const b = document.createElement('button');
+            b.textContent = action + ' ' + author;
+            b.onclick = async () => {
+              const reason = prompt('Reason to ' + action + ' ' + author + ':');
+              if (reason === null) return;
+              const res = await moderationRequest(base + '/moderation/users/' + encodeURIComponent(author) + '/' + action,
+                'POST', { reason });
+              if (!res.ok) alert('Failed: ' + await res.text());
+            };
+            wrap.appendChild(b);
+          });
+        }
+      }
+      return wrap;
+    }
+
     async function loadThreadDetail(threadId) {
-      const res = await fetch('/threads/' + encodeURIComponent(threadId));
+      const res = await fetch(base + '/threads/' + encodeURIComponent(threadId), { headers: userHeaders() });
       if (!res.ok) {
         console.error('Failed to load thread detail:', res.status);
         return;
       }
       const detail = await res.json();
+      currentThreadLocked = detail.locked;
+      currentCategoryLocked = detail.category_locked;
+      updateModerationControls();
+      currentThreadTags = detail.tags || [];
+      const starter = detail.posts.length ? detail.posts[0].author : '';
+      const canTag = !readOnly && !detail.locked && !detail.category_locked
+        && (isModerator || (starter !== '' && starter === getUsername()));
+      document.getElementById('edit-tags').style.display = canTag ? 'inline-block' : 'none';
+      const tagsDiv = document.getElementById('thread-tags');
+      tagsDiv.innerHTML = '';
+      tagsDiv.appendChild(renderTags(currentThreadTags));
       const container = document.getElementById('thread-posts');
       container.innerHTML = '';
       detail.posts.forEach(post => {
         const div = document.createElement('div');
-        div.className = 'post';
+        div.className = post.id === detail.best_answer_id ? 'post best-answer' : 'post';
         const header = document.createElement('div');
         header.innerHTML = '<span class="post-title">' + (post.title || '(no title)') +
-          '</span> <span class="muted">by</span> <span class="post-author">' + (post.author || 'Anonymous') + '</span>';
+          '</span> <span class="muted">by</span> <span class="post-author">' + (post.author || 'Anonymous') + '</span>' +
+          (post.id === detail.best_answer_id ? ' <span class="muted">✔ Best answer</span>' : '') +
+          (post.status === 'pending' ? ' <span class="post-status">awaiting approval</span>' : '') +
+          (post.status === 'hidden' ? ' <span class="post-status">hidden</span>' : '');
         const body = document.createElement('div');
         body.className = 'post-body';
         body.textContent = post.body || '';
         div.appendChild(header);
         div.appendChild(body);
+        if (Array.isArray(post.attachments) && post.attachments.length > 0) {
+          div.appendChild(renderAttachments(post));
+        }
+        div.appendChild(renderPostActions(post));
         container.appendChild(div);
       });
-      markThreadRead(threadId, Array.isArray(detail.posts) ? detail.posts.length : 0);
+      await markThreadRead(threadId);
     }
 
+    document.getElementById('edit-tags').addEventListener('click', async () => {
+      if (!selectedThreadId) return;
+      const input = prompt('Tags, comma-separated:', currentThreadTags.join(', '));
+      if (input === null) return;
+      const res = await fetch(base + '/threads/' + encodeURIComponent(selectedThreadId) + '/tags', {
+        method: 'PUT',
+        headers: { ...userHeaders(), 'Content-Type': 'application/json' },
+        body: JSON.stringify({ tags: input })
+      });
+      if (!res.ok) {
+        alert('Failed to update tags: ' + await res.text());
+        return;
+      }
+      await loadThreadDetail(selectedThreadId);
+      await loadTags();
+      if (selectedCategoryId) await loadThreads(selectedCategoryId);
+    });
+
+    document.getElementById('thread-sort').addEventListener('change', () => {
+      if (selectedCategoryId) loadThreads(selectedCategoryId).catch(console.error);
+    });
+
+    document.getElementById('mark-category-read').addEventListener('click', async () => {
+      if (!selectedCategoryId) return;
+      await fetch(base + '/categories/' + encodeURIComponent(selectedCategoryId) + '/read', {
+        method: 'POST',
+        headers: userHeaders()
+      });
+      await loadThreads(selectedCategoryId);
+      await loadUnreadCounts();
+    });
+
     document.getElementById('new-thread-submit').addEventListener('click', async () => {
       const status = document.getElementById('new-thread-status');
       status.textContent = '';
@@ -262,26 +798,32 @@ pub async fn index() -> Html<String> {
         status.textContent = 'Title and body are required.';
         return;
       }
-      const author = getUsername();
-      const res = await fetch('/threads', {
+      const filesField = document.getElementById('new-thread-files');
+      const tagsField = document.getElementById('new-thread-tags');
+      const res = await fetch(base + '/threads', {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify({
+        headers: userHeaders(),
+        body: buildPostForm({
           category_id: selectedCategoryId,
           title,
-          author,
-          body
-        })
+          body,
+          tags: tagsField.value || ''
+        }, filesField)
       });
       if (!res.ok) {
         const txt = await res.text();
         status.textContent = 'Failed: ' + txt;
         return;
       }
-      status.textContent = 'Thread posted.';
+      status.textContent = res.status === 202
+        ? 'Thread submitted; it will appear once a moderator approves it.'
+        : 'Thread posted.';
       titleField.value = '';
       bodyField.value = '';
+      filesField.value = '';
+      tagsField.value = '';
       await loadThreads(selectedCategoryId);
+      await loadTags();
     });
 
     document.getElementById('reply-submit').addEventListener('click', async () => {
@@ -297,29 +839,109 @@ pub async fn index() -> Html<String> {
         status.textContent = 'Reply body is required.';
         return;
       }
-      const author = getUsername();
-      const res = await fetch('/threads/' + encodeURIComponent(selectedThreadId) + '/replies', {
+      const filesField = document.getElementById('reply-files');
+      const res = await fetch(base + '/threads/' + encodeURIComponent(selectedThreadId) + '/replies', {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify({ author, body })
+        headers: userHeaders(),
+        body: buildPostForm({ body }, filesField)
       });
       if (!res.ok) {
         const txt = await res.text();
         status.textContent = 'Failed: ' + txt;
         return;
       }
-      status.textContent = 'Reply posted.';
+      status.textContent = res.status === 202
+        ? 'Reply submitted; it will appear once a moderator approves it.'
+        : 'Reply posted.';
       bodyField.value = '';
+      filesField.value = '';
       await loadThreadDetail(selectedThreadId);
     });
 
+    async function openThreadFromHash() {
+      const m = location.hash.match(/^#thread=(.+)$/);
+      if (!m) return;
+      const res = await fetch(base + '/threads/' + encodeURIComponent(decodeURIComponent(m[1])), {
+        headers: userHeaders()
+      });
+      if (!res.ok) return;
+      const detail = await res.json();
+      await selectThread({ id: detail.id, title: detail.title });
+    }
+
+    window.addEventListener('hashchange', () => openThreadFromHash().catch(console.error));
+
+    function updateThreadPostCount(threadId, postCount) {
+      document.querySelectorAll('#threads a[data-thread-id]').forEach(a => {
+        if (a.dataset.threadId !== threadId) return;
+        const added = postCount - Number(a.dataset.postCount || '0');
+        a.dataset.postCount = String(postCount);
+        a.dataset.unread = String(Number(a.dataset.unread || '0') + Math.max(added, 0));
+        a.textContent = a.textContent.replace(/\(\d+ posts\)$/, '(' + postCount + ' posts)');
+      });
+      updateThreadListReadStyles();
+    }
+
+    function subscribeToEvents() {
+      if (!window.EventSource) return;
+      const source = new EventSource(base + '/events');
+      source.addEventListener('thread_created', (e) => {
+        const ev = JSON.parse(e.data);
+        if (ev.category_id === selectedCategoryId) {
+          loadThreads(selectedCategoryId).catch(console.error);
+        }
+        loadUnreadCounts().catch(console.error);
+        loadNotifications().catch(console.error);
+      });
+      source.addEventListener('reply_created', (e) => {
+        const ev = JSON.parse(e.data);
+        updateThreadPostCount(ev.thread_id, ev.post_count);
+        if (ev.thread_id === selectedThreadId) {
+          loadThreadDetail(selectedThreadId).catch(console.error);
+        } else {
+          loadUnreadCounts().catch(console.error);
+        }
+        loadNotifications().catch(console.error);
+      });
+      source.addEventListener('thread_removed', (e) => {
+        const ev = JSON.parse(e.data);
+        if (ev.category_id === selectedCategoryId) {
+          loadThreads(selectedCategoryId).catch(console.error);
+        }
+        if (ev.thread_id === selectedThreadId) {
+          document.getElementById('thread-posts').innerHTML = '';
+          loadThreadDetail(selectedThreadId).catch(console.error);
+        }
+        loadUnreadCounts().catch(console.error);
+      });
+      source.addEventListener('post_removed', (e) => {
+        const ev = JSON.parse(e.data);
+        updateThreadPostCount(ev.thread_id, ev.post_count);
+        if (ev.thread_id === selectedThreadId) {
+          loadThreadDetail(selectedThreadId).catch(console.error);
+        }
+      });
+      source.addEventListener('lagged', () => {
+        if (selectedCategoryId) loadThreads(selectedCategoryId).catch(console.error);
+        if (selectedThreadId) loadThreadDetail(selectedThreadId).catch(console.error);
+      });
+    }
+
     // Initial load
+    document.getElementById('read-only-note').style.display = readOnly ? 'block' : 'none';
+    document.getElementById('all-forums').style.display = base ? 'block' : 'none';
     loadCategories().catch(console.error);
+    loadTags().catch(console.error);
+    loadNotifications().catch(console.error);
+    loadModeration().catch(console.error);
+    openThreadFromHash().catch(console.error);
+    subscribeToEvents();
   </script>
 </body>
 </html>
 "#
-    .to_string();
+    .replace("__READ_ONLY__", if state.read_only { "true" } else { "false" })
+    .replace("__BASE__", &serde_json::to_string(&state.base_path).unwrap_or_default());
 
     Html(body)
 }
@@ -327,64 +949,91 @@ pub async fn index() -> Html<String> {
 /// List all top-level categories (root child groups).
 pub async fn list_categories(State(state): State<AppState>) -> impl IntoResponse {
     let db = state.db.read().await;
-    let mut out = Vec::new();
+    let out = category_dtos(&db);
+    for category in &out {
+        debug!(category = %category.id, name = %redact(&category.name), "category");
+    }
+    debug!(count = out.len(), "listed categories");
 
-    println!(
-        "[/categories] root group name='{}', children={}",
-        db.root.name,
-        db.root.children.len()
-    );
+    Json(out)
+}
 
-    for (idx, node) in db.root.children.iter().enumerate() {
-        if let NodeRef::Group(g) = node.as_ref() {
-            println!(
-                "  child[{idx}] Group uuid={} name='{}'",
-                g.uuid,
-                g.name
-            );
-            out.push(CategoryDto {
-                id: g.uuid.to_string(),
-                name: g.name.clone(),
-            });
-        } else if let NodeRef::Entry(e) = node.as_ref() {
-            println!(
-                "  child[{idx}] Entry uuid={} title='{}'",
-                e.uuid,
-                e.get_title().unwrap_or("")
-            );
-        }
+/// Header naming the user a request is made for, percent-encoded.
+///
+/// Read tracking is keyed on it; requests without it are anonymous and untracked.
+/// Clients can put any name in it, so it only proves who someone is when
+/// an authenticating proxy sets it, see `trust_user_header`.
+pub const USER_HEADER: &str = "x-forum-user";
+
+/// Middleware dropping the user header from requests that do not come from
+/// one of the trusted proxies, which then are anonymous. With no trusted
+/// proxies every client names itself, and moderation stays disabled.
+pub async fn trust_user_header(
+    State(proxies): State<Arc<Vec<IpAddr>>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !proxies.is_empty() && !proxies.contains(&addr.ip()) {
+        request.headers_mut().remove(USER_HEADER);
     }
+    next.run(request).await
+}
 
-    Json(out)
+/// The user named in the request headers, if any.
+pub fn request_user(headers: &HeaderMap) -> Option<String> {
+    let raw = headers.get(USER_HEADER)?.to_str().ok()?;
+    let user = percent_decode_str(raw).decode_utf8().ok()?.trim().to_string();
+    (!user.is_empty() && user != "Anonymous").then_some(user)
+}
+
+/// `name` acting from the request's peer address, for the audit log.
+fn request_actor(name: &str, addr: SocketAddr) -> Actor {
+    Actor::new(name, Some(addr.ip().to_string()))
+}
+
+#[derive(Deserialize)]
+pub struct ThreadListQuery {
+    /// `score` to list the highest voted threads first.
+    pub sort: Option<String>,
+    /// Comma-separated tags a thread must all carry to be listed.
+    pub tag: Option<String>,
 }
 
 /// List all threads (child groups) in a given category.
+///
+/// When the request names a user, each thread also carries its unread post count.
 pub async fn list_threads_in_category(
     State(state): State<AppState>,
     Path(category_id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<ThreadListQuery>,
 ) -> impl IntoResponse {
     let db = state.db.read().await;
-    println!("[GET /categories/{category_id}/threads]");
     let Some(category) = find_group_by_id(&db.root, &category_id) else {
-        println!("  category not found");
+        debug!(category = %category_id, "category not found");
         return (StatusCode::NOT_FOUND, "Category not found").into_response();
     };
 
-    let mut out = Vec::new();
-    for node in &category.children {
-        if let NodeRef::Group(g) = node.as_ref() {
-            let post_count = count_entries_in_group(g);
-            println!(
-                "  thread group uuid={} name='{}' posts={}",
-                g.uuid, g.name, post_count
-            );
-            out.push(ThreadSummaryDto {
-                id: g.uuid.to_string(),
-                title: g.name.clone(),
-                post_count,
-            });
+    let mut out = thread_summaries(category);
+    if let Some(tag) = &query.tag {
+        let wanted = parse_tag_list(tag);
+        out.retain(|th| matches_tags(&th.tags, &wanted));
+    }
+    if query.sort.as_deref() == Some("score") {
+        out.sort_by_key(|th| std::cmp::Reverse(th.score));
+    }
+    if let Some(user) = request_user(&headers) {
+        let markers = load_markers(&db, &user);
+        for th in &mut out {
+            if let Some(thread) = find_group_by_id(category, &th.id) {
+                th.unread_posts = Some(unread_posts(thread, markers.get(&th.id)));
+            }
         }
     }
+    for th in &out {
+        debug!(thread = %th.id, title = %redact(&th.title), posts = th.post_count, "thread");
+    }
 
     Json(out).into_response()
 }
@@ -393,102 +1042,1408 @@ pub async fn list_threads_in_category(
 pub async fn get_thread_detail(
     State(state): State<AppState>,
     Path(thread_id): Path<String>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     let db = state.db.read().await;
-    println!("[GET /threads/{thread_id}]");
     let Some(thread_group) = find_group_by_id(&db.root, &thread_id) else {
-        println!("  thread not found");
+        debug!(thread = %thread_id, "thread not found");
         return (StatusCode::NOT_FOUND, "Thread not found").into_response();
     };
 
-    let mut posts = Vec::new();
-    for node in &thread_group.children {
-        if let NodeRef::Entry(e) = node.as_ref() {
-            posts.push(entry_to_post_dto(e));
-        }
-    }
-
-    let detail = ThreadDetailDto {
-        id: thread_group.uuid.to_string(),
-        title: thread_group.name.clone(),
-        posts,
-    };
-
+    let viewer = request_user(&headers);
+    let moderator = state.is_moderator(viewer.as_deref());
+    let mut detail = thread_detail(thread_group, viewer.as_deref(), moderator);
+    detail.category_locked = find_category_of(&db.root, &thread_id)
+        .is_some_and(|c| is_category_locked(&db, &c.uuid.to_string()));
     Json(detail).into_response()
 }
 
 #[derive(Deserialize)]
-pub struct CreateThreadRequest {
-    pub category_id: String,
-    pub title: String,
-    pub author: String,
-    pub body: String,
-}
-
-#[derive(Deserialize)]
-pub struct CreateReplyRequest {
-    pub author: String,
-    pub body: String,
+pub struct UpdateTagsRequest {
+    #[serde(deserialize_with = "deserialize_tags")]
+    pub tags: Vec<String>,
 }
 
-/// Create a new thread in a category.
-pub async fn create_thread(
+/// Replace the tags of a thread; only its author or a moderator may.
+pub async fn update_thread_tags(
     State(state): State<AppState>,
-    Json(payload): Json<CreateThreadRequest>,
+    Path(thread_id): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<UpdateTagsRequest>,
 ) -> impl IntoResponse {
-    println!(
-        "[POST /threads] category_id={} title='{}' author='{}'",
-        payload.category_id, payload.title, payload.author
-    );
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(thread = %thread_id, tags = %redact(&payload.tags.join(",")), "updating tags");
     let mut db = state.db.write().await;
-    let thread_id = match add_thread_to_category(
-        &mut db,
-        &payload.category_id,
-        &payload.title,
-        &payload.author,
-        &payload.body,
-    ) {
-        Ok(id) => id,
+    let Some(thread) = find_thread_by_id(&db.root, &thread_id) else {
+        return (StatusCode::NOT_FOUND, "Thread not found").into_response();
+    };
+    let author = thread.entries().first().and_then(|e| e.get_username());
+    if author != Some(user.as_str()) && !state.is_moderator(Some(&user)) {
+        return (StatusCode::FORBIDDEN, "Only the author or a moderator can change the tags")
+            .into_response();
+    }
+    if let Some(reason) = thread_lock_reason(&db, thread) {
+        return (StatusCode::FORBIDDEN, reason).into_response();
+    }
+    let Some(thread) = find_group_by_id_mut(&mut db.root, &thread_id) else {
+        return (StatusCode::NOT_FOUND, "Thread not found").into_response();
+    };
+    let tags = match set_thread_tags(thread, &payload.tags) {
+        Ok(tags) => tags,
         Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
     };
+    let actor = request_actor(&user, addr);
+    record_event(&mut db, &actor, "update_tags", &thread_id, Some(&tags.join(", ")));
 
-    if let Err(e) = save_database(&db, &state.db_path, &state.key) {
-        eprintln!("Failed to save database: {e}");
+    if let Err(e) = state.save(&db) {
+        error!(error = %e, "Failed to save database");
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             "Failed to save database",
         )
             .into_response();
     }
+    Json(tags).into_response()
+}
 
-    (StatusCode::CREATED, thread_id).into_response()
+/// All tags in use, with the number of threads carrying each.
+pub async fn list_tags(State(state): State<AppState>) -> impl IntoResponse {
+    let db = state.db.read().await;
+    Json(tag_counts(&db.root))
 }
 
-/// Create a reply in an existing thread.
-pub async fn create_reply(
+/// Threads carrying a tag, across all categories.
+pub async fn list_threads_with_tag(
     State(state): State<AppState>,
-    Path(thread_id): Path<String>,
-    Json(payload): Json<CreateReplyRequest>,
+    Path(tag): Path<String>,
 ) -> impl IntoResponse {
-    println!(
-        "[POST /threads/{thread_id}/replies] author='{}'",
-        payload.author
-    );
-    let mut db = state.db.write().await;
-    let reply_id =
-        match add_reply_to_thread(&mut db, &thread_id, &payload.author, &payload.body) {
-            Ok(id) => id,
-            Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
-        };
+    let db = state.db.read().await;
+    debug!(tag = %redact(&tag), "listing threads with tag");
+    Json(threads_with_tag(&db.root, &tag))
+}
 
-    if let Err(e) = save_database(&db, &state.db_path, &state.key) {
-        eprintln!("Failed to save database: {e}");
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to save database",
-        )
-            .into_response();
+/// Add the requesting user's reaction of a kind to a post.
+pub async fn add_reaction(
+    State(state): State<AppState>,
+    Path((post_id, kind)): Path<(String, String)>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    update_post_reaction(state, post_id, kind, headers, addr, true).await
+}
+
+/// Remove the requesting user's reaction of a kind from a post.
+pub async fn remove_reaction(
+    State(state): State<AppState>,
+    Path((post_id, kind)): Path<(String, String)>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    update_post_reaction(state, post_id, kind, headers, addr, false).await
+}
+
+async fn update_post_reaction(
+    state: AppState,
+    post_id: String,
+    kind: String,
+    headers: HeaderMap,
+    addr: SocketAddr,
+    on: bool,
+) -> Response {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(post = %post_id, kind = %kind, user = %redact(&user), on, "setting reaction");
+
+    let mut db = state.db.write().await;
+    if is_banned(&db, &user) {
+        return (StatusCode::FORBIDDEN, "You are banned from reacting").into_response();
+    }
+    if !post_visible_to(&db, &post_id, Some(&user), state.is_moderator(Some(&user))) {
+        return (StatusCode::NOT_FOUND, "Post not found").into_response();
+    }
+    if let Some(reason) = post_lock_reason(&db, &post_id) {
+        return (StatusCode::FORBIDDEN, reason).into_response();
     }
+    let Some(entry) = find_entry_by_id_mut(&mut db.root, &post_id) else {
+        return (StatusCode::NOT_FOUND, "Post not found").into_response();
+    };
+    if let Err(msg) = set_reaction(entry, &kind, &user, on) {
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let action = if on { "add_reaction" } else { "remove_reaction" };
+    record_event(&mut db, &request_actor(&user, addr), action, &post_id, Some(&kind));
+    save_user_state(&state, &db)
+}
+
+#[derive(Deserialize)]
+pub struct VoteRequest {
+    /// 1 (up), -1 (down) or 0 (withdraw).
+    pub value: i8,
+}
+
+/// Cast or withdraw the requesting user's vote on a post.
+pub async fn vote_on_post(
+    State(state): State<AppState>,
+    Path(post_id): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<VoteRequest>,
+) -> impl IntoResponse {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(post = %post_id, user = %redact(&user), value = payload.value, "voting");
+
+    let mut db = state.db.write().await;
+    if is_banned(&db, &user) {
+        return (StatusCode::FORBIDDEN, "You are banned from voting").into_response();
+    }
+    if !post_visible_to(&db, &post_id, Some(&user), state.is_moderator(Some(&user))) {
+        return (StatusCode::NOT_FOUND, "Post not found").into_response();
+    }
+    if let Some(reason) = post_lock_reason(&db, &post_id) {
+        return (StatusCode::FORBIDDEN, reason).into_response();
+    }
+    let Some(entry) = find_entry_by_id_mut(&mut db.root, &post_id) else {
+        return (StatusCode::NOT_FOUND, "Post not found").into_response();
+    };
+    if let Err(msg) = set_vote(entry, &user, payload.value) {
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let value = payload.value.to_string();
+    record_event(&mut db, &request_actor(&user, addr), "vote", &post_id, Some(&value));
+    save_user_state(&state, &db)
+}
+
+/// Unread threads and per-category unread counts of the requesting user.
+pub async fn unread(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(user = %redact(&user), "listing unread posts");
+
+    let db = state.db.read().await;
+    Json(unread_summary(&db, &user)).into_response()
+}
+
+/// Mark all posts of a thread read for the requesting user.
+pub async fn mark_thread_read(
+    State(state): State<AppState>,
+    Path(thread_id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(thread = %thread_id, user = %redact(&user), "marking thread read");
+
+    let mut db = state.db.write().await;
+    match readstate::mark_thread_read(&mut db, &user, &thread_id) {
+        Ok(false) => StatusCode::NO_CONTENT.into_response(),
+        Ok(true) => save_user_state(&state, &db),
+        Err(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
+    }
+}
+
+/// Mark all threads of a category read for the requesting user.
+pub async fn mark_category_read(
+    State(state): State<AppState>,
+    Path(category_id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(category = %category_id, user = %redact(&user), "marking category read");
+
+    let mut db = state.db.write().await;
+    match readstate::mark_category_read(&mut db, &user, &category_id) {
+        Ok(_) => save_user_state(&state, &db),
+        Err(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
+    }
+}
+
+/// Save after a change to per-user state, answering 204 on success.
+fn save_user_state(state: &AppState, db: &keepass::Database) -> Response {
+    if let Err(e) = state.save(db) {
+        error!(error = %e, "Failed to save database");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to save database",
+        )
+            .into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct NotificationsQuery {
+    #[serde(default)]
+    pub unread: bool,
+}
+
+/// Notification inbox of the requesting user, newest first.
+pub async fn notifications(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<NotificationsQuery>,
+) -> impl IntoResponse {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(user = %redact(&user), unread_only = query.unread, "listing notifications");
+
+    let db = state.db.read().await;
+    Json(notifications_for(&db, &user, query.unread)).into_response()
+}
+
+/// Mark every notification of the requesting user read.
+pub async fn mark_all_notifications_read(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(user = %redact(&user), "marking all notifications read");
+
+    let mut db = state.db.write().await;
+    match mark_notifications_read(&mut db, &user, None) {
+        Ok(()) => save_user_state(&state, &db),
+        Err(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
+    }
+}
+
+/// Mark one notification of the requesting user read.
+pub async fn mark_notification_read(
+    State(state): State<AppState>,
+    Path(notification_id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(notification = %notification_id, user = %redact(&user), "marking notification read");
+
+    let mut db = state.db.write().await;
+    match mark_notifications_read(&mut db, &user, Some(&notification_id)) {
+        Ok(()) => save_user_state(&state, &db),
+        Err(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
+    }
+}
+
+/// Watch a thread: the requesting user is notified of its replies.
+pub async fn watch_thread(
+    State(state): State<AppState>,
+    Path(thread_id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    set_thread_watch(state, thread_id, headers, true).await
+}
+
+/// Stop watching a thread.
+pub async fn unwatch_thread(
+    State(state): State<AppState>,
+    Path(thread_id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    set_thread_watch(state, thread_id, headers, false).await
+}
+
+async fn set_thread_watch(
+    state: AppState,
+    thread_id: String,
+    headers: HeaderMap,
+    watch: bool,
+) -> Response {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(thread = %thread_id, user = %redact(&user), watch, "setting watch");
+
+    let mut db = state.db.write().await;
+    match set_watching(&mut db, &user, &thread_id, watch) {
+        Ok(()) => save_user_state(&state, &db),
+        Err(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
+    }
+}
+
+/// The requesting user if they are a moderator; 401 or 403 otherwise.
+///
+/// Like every identity in the forum, this trusts the user header, which is
+/// why moderators can only be configured together with trusted proxies.
+fn require_moderator(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<String, (StatusCode, &'static str)> {
+    let Some(user) = request_user(headers) else {
+        return Err((StatusCode::UNAUTHORIZED, "No user given"));
+    };
+    if !state.is_moderator(Some(&user)) {
+        return Err((StatusCode::FORBIDDEN, "Moderators only"));
+    }
+    Ok(user)
+}
+
+/// Answer a failed moderation request: 404 for unknown targets, 400 otherwise.
+fn moderation_error(msg: String) -> Response {
+    let status = if msg.ends_with("not found") {
+        StatusCode::NOT_FOUND
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    (status, msg).into_response()
+}
+
+#[derive(Deserialize, Default)]
+pub struct ModerationReason {
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Report a post to the moderators.
+pub async fn report_post(
+    State(state): State<AppState>,
+    Path(post_id): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    payload: Option<Json<ModerationReason>>,
+) -> impl IntoResponse {
+    let Some(user) = request_user(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No user given").into_response();
+    };
+    debug!(post = %post_id, user = %redact(&user), "reporting post");
+
+    let mut db = state.db.write().await;
+    if is_banned(&db, &user) {
+        return (StatusCode::FORBIDDEN, "You are banned from reporting").into_response();
+    }
+    if !post_visible_to(&db, &post_id, Some(&user), state.is_moderator(Some(&user))) {
+        return (StatusCode::NOT_FOUND, "Post not found").into_response();
+    }
+    let reason = payload.map(|Json(p)| p.reason).unwrap_or_default();
+    let report_id = match file_report(&mut db, &post_id, &user, &reason) {
+        Ok(id) => id,
+        Err(msg) => return moderation_error(msg),
+    };
+    let actor = request_actor(&user, addr);
+    record_event(&mut db, &actor, "report_post", &post_id, Some(&report_id));
+    if let Err(e) = state.save(&db) {
+        error!(error = %e, "Failed to save database");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to save database",
+        )
+            .into_response();
+    }
+    (StatusCode::CREATED, report_id).into_response()
+}
+
+/// Moderators and the moderation settings.
+pub async fn moderation_settings(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_moderator(&state, &headers) {
+        return rejection.into_response();
+    }
+    let db = state.db.read().await;
+    Json(ModerationOverviewDto {
+        moderators: state.moderators.iter().cloned().collect(),
+        settings: load_settings(&db),
+    })
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ReportsQuery {
+    /// Include resolved reports.
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// Reports about posts, newest first; only open ones unless `all=true`.
+pub async fn list_reports(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ReportsQuery>,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_moderator(&state, &headers) {
+        return rejection.into_response();
+    }
+    let db = state.db.read().await;
+    Json(reports(&db, query.all)).into_response()
+}
+
+/// Close a report without acting on the post.
+pub async fn dismiss_post_report(
+    State(state): State<AppState>,
+    Path(report_id): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    payload: Option<Json<ModerationReason>>,
+) -> impl IntoResponse {
+    let moderator = match require_moderator(&state, &headers) {
+        Ok(user) => request_actor(&user, addr),
+        Err(rejection) => return rejection.into_response(),
+    };
+    debug!(report = %report_id, moderator = %redact(&moderator.name), "dismissing report");
+
+    let reason = payload.map(|Json(p)| p.reason).unwrap_or_default();
+    let mut db = state.db.write().await;
+    match dismiss_report(&mut db, &report_id, &moderator, Some(&reason)) {
+        Ok(()) => save_user_state(&state, &db),
+        Err(msg) => moderation_error(msg),
+    }
+}
+
+/// Posts waiting for approval, oldest first.
+pub async fn moderation_queue(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_moderator(&state, &headers) {
+        return rejection.into_response();
+    }
+    let db = state.db.read().await;
+    Json(pending_posts(&db)).into_response()
+}
+
+/// Approve, hide, unhide or delete a post.
+pub async fn moderate_post_action(
+    State(state): State<AppState>,
+    Path((post_id, action)): Path<(String, String)>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    payload: Option<Json<ModerationReason>>,
+) -> impl IntoResponse {
+    let moderator = match require_moderator(&state, &headers) {
+        Ok(user) => request_actor(&user, addr),
+        Err(rejection) => return rejection.into_response(),
+    };
+    debug!(post = %post_id, action = %action, moderator = %redact(&moderator.name), "moderating post");
+    let action = match PostAction::parse(&action) {
+        Ok(action) => action,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    let reason = payload.map(|Json(p)| p.reason).unwrap_or_default();
+    let mut db = state.db.write().await;
+    let event = match moderate_post(&mut db, &post_id, action, &moderator, Some(&reason)) {
+        Ok(event) => event,
+        Err(msg) => return moderation_error(msg),
+    };
+    if let Some(event) = &event {
+        enqueue_post_notifications(&mut db, &state.outbound, event);
+    }
+
+    let response = save_user_state(&state, &db);
+    if response.status().is_success()
+        && let Some(event) = event
+    {
+        state.publish(event);
+        state.outbox_wake.notify_one();
+    }
+    response
+}
+
+/// Lock a thread against new replies.
+pub async fn lock_thread(
+    State(state): State<AppState>,
+    Path(thread_id): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    payload: Option<Json<ModerationReason>>,
+) -> impl IntoResponse {
+    set_thread_lock(state, thread_id, headers, addr, payload, true).await
+}
+
+/// Unlock a thread.
+pub async fn unlock_thread(
+    State(state): State<AppState>,
+    Path(thread_id): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    payload: Option<Json<ModerationReason>>,
+) -> impl IntoResponse {
+    set_thread_lock(state, thread_id, headers, addr, payload, false).await
+}
+
+async fn set_thread_lock(
+    state: AppState,
+    thread_id: String,
+    headers: HeaderMap,
+    addr: SocketAddr,
+    payload: Option<Json<ModerationReason>>,
+    locked: bool,
+) -> Response {
+    let moderator = match require_moderator(&state, &headers) {
+        Ok(user) => request_actor(&user, addr),
+        Err(rejection) => return rejection.into_response(),
+    };
+    debug!(thread = %thread_id, moderator = %redact(&moderator.name), locked, "locking thread");
+
+    let reason = payload.map(|Json(p)| p.reason).unwrap_or_default();
+    let mut db = state.db.write().await;
+    match set_thread_locked(&mut db, &thread_id, locked, &moderator, Some(&reason)) {
+        Ok(()) => save_user_state(&state, &db),
+        Err(msg) => moderation_error(msg),
+    }
+}
+
+/// Warn, ban, unban, trust or untrust a user.
+pub async fn moderate_user_action(
+    State(state): State<AppState>,
+    Path((user, action)): Path<(String, String)>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    payload: Option<Json<ModerationReason>>,
+) -> impl IntoResponse {
+    let moderator = match require_moderator(&state, &headers) {
+        Ok(user) => request_actor(&user, addr),
+        Err(rejection) => return rejection.into_response(),
+    };
+    debug!(
+        user = %redact(&user),
+        action = %action,
+        moderator = %redact(&moderator.name),
+        "moderating user"
+    );
+    let action = match UserAction::parse(&action) {
+        Ok(action) => action,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    let reason = payload.map(|Json(p)| p.reason).unwrap_or_default();
+    let mut db = state.db.write().await;
+    match moderate_user(&mut db, &user, action, &moderator, Some(&reason)) {
+        Ok(()) => save_user_state(&state, &db),
+        Err(msg) => moderation_error(msg),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CategoryModerationRequest {
+    pub require_approval: Option<bool>,
+    /// Close the category to new threads and replies, or open it again.
+    pub locked: Option<bool>,
+}
+
+/// Require approval of posts by untrusted users in a category, or stop
+/// requiring it; lock or unlock the category. Fields left out stay as they are.
+pub async fn update_category_moderation(
+    State(state): State<AppState>,
+    Path(category_id): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<CategoryModerationRequest>,
+) -> impl IntoResponse {
+    let moderator = match require_moderator(&state, &headers) {
+        Ok(user) => request_actor(&user, addr),
+        Err(rejection) => return rejection.into_response(),
+    };
+    debug!(
+        category = %category_id,
+        moderator = %redact(&moderator.name),
+        require_approval = ?payload.require_approval,
+        locked = ?payload.locked,
+        "updating category moderation"
+    );
+
+    let mut db = state.db.write().await;
+    let mut result = Ok(());
+    if let Some(required) = payload.require_approval {
+        result = set_category_approval(&mut db, &category_id, required, &moderator);
+    }
+    if let (Ok(()), Some(locked)) = (&result, payload.locked) {
+        result = set_category_locked(&mut db, &category_id, locked, &moderator);
+    }
+    match result {
+        Ok(()) => save_user_state(&state, &db),
+        Err(msg) => moderation_error(msg),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    /// Only the newest this many records.
+    pub limit: Option<usize>,
+}
+
+/// The audit log, newest first, for moderators. Served at both
+/// `/moderation/audit` and `/admin/audit`.
+pub async fn moderation_audit_log(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_moderator(&state, &headers) {
+        return rejection.into_response();
+    }
+    let db = state.db.read().await;
+    Json(audit_log(&db, query.limit)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct VerifyAuditQuery {
+    /// A head hash noted down earlier that must still be part of the log.
+    pub head: Option<String>,
+}
+
+/// Check the audit log's hash chain, for moderators.
+pub async fn verify_audit(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<VerifyAuditQuery>,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_moderator(&state, &headers) {
+        return rejection.into_response();
+    }
+    let db = state.db.read().await;
+    Json(verify_audit_log(&db, query.head.as_deref())).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CreateThreadRequest {
+    pub category_id: String,
+    pub title: String,
+    pub body: String,
+    /// A list, or one comma-separated string.
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateReplyRequest {
+    pub body: String,
+}
+
+/// A file part received in a multipart post submission.
+pub struct Upload {
+    pub file_name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Request body for creating threads and replies.
+///
+/// Accepts either a JSON body, or `multipart/form-data` where text parts
+/// fill the payload fields and file parts become attachments.
+pub struct PostSubmission<T> {
+    pub payload: T,
+    pub uploads: Vec<Upload>,
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for PostSubmission<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_multipart = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("multipart/form-data"));
+
+        if !is_multipart {
+            let Json(payload) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            return Ok(Self {
+                payload,
+                uploads: Vec::new(),
+            });
+        }
+
+        let mut multipart = Multipart::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let bad_request = |e: axum::extract::multipart::MultipartError| {
+            (StatusCode::BAD_REQUEST, e.body_text()).into_response()
+        };
+
+        let mut fields = serde_json::Map::new();
+        let mut uploads = Vec::new();
+        while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+            let name = field.name().unwrap_or("").to_string();
+            if let Some(file_name) = field.file_name().map(str::to_string) {
+                let content_type = field
+                    .content_type()
+                    .unwrap_or("application/octet-stream")
+                    .to_string();
+                let data = field.bytes().await.map_err(bad_request)?.to_vec();
+                if file_name.is_empty() && data.is_empty() {
+                    continue;
+                }
+                uploads.push(Upload {
+                    file_name,
+                    content_type,
+                    data,
+                });
+            } else {
+                let text = field.text().await.map_err(bad_request)?;
+                fields.insert(name, serde_json::Value::String(text));
+            }
+        }
+
+        let payload = serde_json::from_value(serde_json::Value::Object(fields))
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+        Ok(Self { payload, uploads })
+    }
+}
+
+/// Validate uploads against the configured limits.
+fn validate_uploads(
+    limits: &AttachmentLimits,
+    uploads: Vec<Upload>,
+) -> Result<Vec<NewAttachment>, String> {
+    if uploads.len() > limits.max_per_post {
+        return Err(format!(
+            "Too many attachments ({}), limit is {}",
+            uploads.len(),
+            limits.max_per_post
+        ));
+    }
+    uploads
+        .into_iter()
+        .map(|u| limits.validate(&u.file_name, &u.content_type, u.data))
+        .collect()
+}
+
+/// Create a new thread in a category, written by the `X-Forum-User` or by
+/// Anonymous.
+///
+/// Threads by untrusted users in categories that require approval are held:
+/// the answer is 202 and the thread stays invisible until a moderator approves it.
+pub async fn create_thread(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    submission: PostSubmission<CreateThreadRequest>,
+) -> impl IntoResponse {
+    let PostSubmission { payload, uploads } = submission;
+    let author = request_user(&headers).unwrap_or_else(|| "Anonymous".to_string());
+    debug!(
+        category = %payload.category_id,
+        title = %redact(&payload.title),
+        author = %redact(&author),
+        body = %redact(&payload.body),
+        attachments = uploads.len(),
+        "creating thread"
+    );
+    let attachments = match validate_uploads(&state.attachment_limits, uploads) {
+        Ok(a) => a,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    let mut db = state.db.write().await;
+    if is_banned(&db, &author) {
+        return (StatusCode::FORBIDDEN, "You are banned from posting").into_response();
+    }
+    if is_category_locked(&db, &payload.category_id) {
+        return (StatusCode::FORBIDDEN, "Category is locked").into_response();
+    }
+    let held = requires_approval(
+        &db,
+        &payload.category_id,
+        &author,
+        state.is_moderator(Some(&author)),
+    );
+    let thread_id = match add_thread_to_category(
+        &mut db,
+        &payload.category_id,
+        &payload.title,
+        &author,
+        &payload.body,
+        &payload.tags,
+        &attachments,
+        held,
+        true,
+    ) {
+        Ok(id) => id,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let detail = format!(
+        "category {}{}",
+        payload.category_id,
+        if held { ", held for approval" } else { "" }
+    );
+    let actor = request_actor(&author, addr);
+    record_event(&mut db, &actor, "create_thread", &thread_id, Some(&detail));
+
+    let event = find_group_by_id(&db.root, &thread_id)
+        .filter(|_| !held)
+        .map(|thread_group| {
+        ForumEvent::ThreadCreated {
+            category_id: payload.category_id.clone(),
+            thread_id: thread_id.clone(),
+            post_id: thread_group
+                .entries()
+                .first()
+                .map(|e| e.uuid.to_string())
+                .unwrap_or_default(),
+            title: payload.title.clone(),
+            post_count: count_entries_in_group(thread_group),
+        }
+    });
+    if let Some(event) = &event {
+        enqueue_post_notifications(&mut db, &state.outbound, event);
+    }
+
+    if let Err(e) = state.save(&db) {
+        error!(error = %e, "Failed to save database");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to save database",
+        )
+            .into_response();
+    }
+
+    if let Some(event) = event {
+        state.publish(event);
+        state.outbox_wake.notify_one();
+    }
+
+    let status = if held {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::CREATED
+    };
+    (status, thread_id).into_response()
+}
+
+/// Create a reply in an existing thread, written by the `X-Forum-User` or
+/// by Anonymous.
+///
+/// Locked threads refuse replies. Replies held for approval are answered with 202.
+pub async fn create_reply(
+    State(state): State<AppState>,
+    Path(thread_id): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    submission: PostSubmission<CreateReplyRequest>,
+) -> impl IntoResponse {
+    let PostSubmission { payload, uploads } = submission;
+    let author = request_user(&headers).unwrap_or_else(|| "Anonymous".to_string());
+    debug!(
+        thread = %thread_id,
+        author = %redact(&author),
+        body = %redact(&payload.body),
+        attachments = uploads.len(),
+        "creating reply"
+    );
+    let attachments = match validate_uploads(&state.attachment_limits, uploads) {
+        Ok(a) => a,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    let mut db = state.db.write().await;
+    if is_banned(&db, &author) {
+        return (StatusCode::FORBIDDEN, "You are banned from posting").into_response();
+    }
+    if find_group_by_id(&db.root, &thread_id).is_some_and(is_thread_locked) {
+        return (StatusCode::FORBIDDEN, "Thread is locked").into_response();
+    }
+    if find_category_of(&db.root, &thread_id)
+        .is_some_and(|c| is_category_locked(&db, &c.uuid.to_string()))
+    {
+        return (StatusCode::FORBIDDEN, "Category is locked").into_response();
+    }
+    let held = find_category_of(&db.root, &thread_id).is_some_and(|category| {
+        requires_approval(
+            &db,
+            &category.uuid.to_string(),
+            &author,
+            state.is_moderator(Some(&author)),
+        )
+    });
+    let reply_id = match add_reply_to_thread(
+        &mut db,
+        &thread_id,
+        &author,
+        &payload.body,
+        &attachments,
+        held,
+        true,
+    ) {
+        Ok(id) => id,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let detail = format!(
+        "thread {thread_id}{}",
+        if held { ", held for approval" } else { "" }
+    );
+    let actor = request_actor(&author, addr);
+    record_event(&mut db, &actor, "create_reply", &reply_id, Some(&detail));
+
+    let event = match (
+        find_category_of(&db.root, &thread_id),
+        find_group_by_id(&db.root, &thread_id),
+    ) {
+        _ if held => None,
+        (Some(category), Some(thread_group)) => Some(ForumEvent::ReplyCreated {
+            category_id: category.uuid.to_string(),
+            thread_id: thread_id.clone(),
+            post_id: reply_id.clone(),
+            post_count: count_entries_in_group(thread_group),
+        }),
+        _ => None,
+    };
+    if let Some(event) = &event {
+        enqueue_post_notifications(&mut db, &state.outbound, event);
+    }
+
+    if let Err(e) = state.save(&db) {
+        error!(error = %e, "Failed to save database");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to save database",
+        )
+            .into_response();
+    }
+
+    if let Some(event) = event {
+        state.publish(event);
+        state.outbox_wake.notify_one();
+    }
+
+    let status = if held {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::CREATED
+    };
+    (status, reply_id).into_response()
+}
+
+/// Download a single attachment of a post.
+pub async fn get_post_attachment(
+    State(state): State<AppState>,
+    Path((post_id, name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let db = state.db.read().await;
+    debug!(post = %post_id, name = %redact(&name), "fetching attachment");
+    let viewer = request_user(&headers);
+    let Some(entry) = find_entry_by_id(&db.root, &post_id)
+        .filter(|e| can_see(e, viewer.as_deref(), state.is_moderator(viewer.as_deref())))
+    else {
+        return (StatusCode::NOT_FOUND, "Post not found").into_response();
+    };
+    let Some((content_type, data)) = get_attachment(entry, &name) else {
+        return (StatusCode::NOT_FOUND, "Attachment not found").into_response();
+    };
+
+    let disposition = if content_type.starts_with("image/") {
+        "inline"
+    } else {
+        "attachment"
+    };
+    let file_name: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() || c == ' ' => c,
+            _ => '_',
+        })
+        .collect();
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("{disposition}; filename=\"{file_name}\""),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+/// Liveness: the process is up and serving requests.
+pub async fn healthz() -> impl IntoResponse {
+    "ok"
+}
+
+/// How long `/readyz` waits for the database lock before calling the
+/// instance wedged.
+const READY_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Readiness: the database lock can be taken and the last save succeeded.
+/// Answers 503 otherwise, so a service manager can restart the instance.
+pub async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let database_unlocked = tokio::time::timeout(READY_LOCK_TIMEOUT, state.db.read())
+        .await
+        .is_ok();
+    let last_save_error = state.last_save_error.lock().ok().and_then(|e| e.clone());
+    let last_save_ok = last_save_error.is_none();
+    let ready = database_unlocked && last_save_ok;
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = ReadinessDto {
+        ready,
+        database_unlocked,
+        last_save_ok,
+        last_save_error,
+    };
+    (status, Json(body))
+}
+
+/// How long an integrity report is served again instead of re-opening the
+/// file, which runs the whole key derivation.
+const INTEGRITY_REPORT_TTL: Duration = Duration::from_secs(60);
+
+/// Re-open the database file with the server's key, compare it with the
+/// in-memory tree and look for structural problems, for moderators. The
+/// report is reused for `INTEGRITY_REPORT_TTL`.
+pub async fn integrity(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Err(rejection) = require_moderator(&state, &headers) {
+        return rejection.into_response();
+    }
+    let mut cached = state.integrity_report.lock().await;
+    if let Some((made, report)) = cached.as_ref()
+        && made.elapsed() < INTEGRITY_REPORT_TTL
+    {
+        return Json(report.clone()).into_response();
+    }
+
+    // Saves happen under the write lock, so holding the read lock keeps
+    // the file and the tree in step while the file is read back.
+    let db = state.db.read().await;
+    let (path, key) = (state.db_path.clone(), state.key.clone());
+    let disk = tokio::task::spawn_blocking(move || {
+        open_database(&path, &key).map_err(|e| format!("Failed to open database file: {e}"))
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Integrity check failed: {e}")));
+
+    let report = check_integrity(&db, Some(disk));
+    if !report.ok {
+        warn!(
+            disk_readable = report.disk_readable,
+            missing_on_disk = report.missing_on_disk.len(),
+            missing_in_memory = report.missing_in_memory.len(),
+            changed = report.changed.len(),
+            orphan_entries = report.orphan_entries.len(),
+            empty_threads = report.empty_threads.len(),
+            duplicate_uuids = report.duplicate_uuids.len(),
+            "Integrity check found problems"
+        );
+    }
+    *cached = Some((Instant::now(), report.clone()));
+    Json(report).into_response()
+}
+
+/// Prometheus metrics: request, lock and save timings plus the size of the forum.
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut out = state.metrics.render();
+
+    let (mut category_count, mut thread_count, mut post_count) = (0usize, 0, 0);
+    {
+        let db = state.db.read().await;
+        for category in categories(&db.root) {
+            category_count += 1;
+            thread_count += category.groups().len();
+            post_count += count_entries_in_group(category);
+        }
+    }
+    let file_size = tokio::fs::metadata(&state.db_path)
+        .await
+        .map_or(0, |m| m.len());
+
+    render_gauge(
+        &mut out,
+        "kdbx_forum_db_file_size_bytes",
+        "Size of the database file.",
+        file_size,
+    );
+    render_gauge(
+        &mut out,
+        "kdbx_forum_categories",
+        "Number of categories.",
+        category_count as u64,
+    );
+    render_gauge(&mut out, "kdbx_forum_threads", "Number of threads.", thread_count as u64);
+    render_gauge(
+        &mut out,
+        "kdbx_forum_posts",
+        "Number of published posts.",
+        post_count as u64,
+    );
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        out,
+    )
+}
+
+/// Admin view of attachment storage, in total and per category, for moderators.
+pub async fn attachment_usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(rejection) = require_moderator(&state, &headers) {
+        return rejection.into_response();
+    }
+    let db = state.db.read().await;
+    let (count, total_bytes) = attachment_usage_in_group(&db.root);
+
+    let categories = categories(&db.root)
+        .map(|g| {
+            let (count, total_bytes) = attachment_usage_in_group(g);
+            CategoryAttachmentUsageDto {
+                id: g.uuid.to_string(),
+                name: g.name.clone(),
+                count,
+                total_bytes,
+            }
+        })
+        .collect();
+
+    Json(AttachmentUsageDto {
+        count,
+        total_bytes,
+        categories,
+    })
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ExportRequest {
+    #[serde(default)]
+    pub category_ids: Vec<String>,
+    #[serde(default)]
+    pub thread_ids: Vec<String>,
+    pub password: String,
+}
+
+/// Export selected categories/threads as a new .kdbx protected by its own
+/// password, for moderators only: the copy leaves the forum's key behind.
+pub async fn export_subtree(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<ExportRequest>,
+) -> impl IntoResponse {
+    let user = match require_moderator(&state, &headers) {
+        Ok(user) => user,
+        Err(rejection) => return rejection.into_response(),
+    };
+    debug!(
+        categories = payload.category_ids.len(),
+        threads = payload.thread_ids.len(),
+        "exporting subtree"
+    );
+    if payload.password.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Export password is required").into_response();
+    }
+
+    let exported = {
+        let mut db = state.db.write().await;
+        let exported =
+            match build_export_database(&db, &payload.category_ids, &payload.thread_ids) {
+                Ok(exported) => exported,
+                Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+            };
+        // Exports leave the forum under a new key, so they are audited like key changes.
+        let actor = request_actor(&user, addr);
+        let detail = format!(
+            "{} categories, {} threads",
+            payload.category_ids.len(),
+            payload.thread_ids.len()
+        );
+        let target = db.root.uuid.to_string();
+        record_event(&mut db, &actor, "export", &target, Some(&detail));
+        if let Err(e) = state.save(&db) {
+            error!(error = %e, "Failed to save database");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to save database",
+            )
+                .into_response();
+        }
+        exported
+    };
+
+    let key = DatabaseKey::new().with_password(&payload.password);
+    let bytes = match tokio::task::spawn_blocking(move || database_to_bytes(&exported, &key)).await
+    {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(msg)) => {
+            error!("{msg}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response();
+        }
+        Err(e) => {
+            error!(error = %e, "Export task failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Export failed").into_response();
+        }
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"kdbx-forum-export.kdbx\"",
+            ),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    pub token: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Check the feed token in the query string. Feeds are disabled when no tokens are configured.
+fn authorize_feed<'a>(
+    state: &'a AppState,
+    query: &FeedQuery,
+) -> Result<&'a str, (StatusCode, &'static str)> {
+    if state.feed_tokens.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "Feeds are not enabled"));
+    }
+    query
+        .token
+        .as_deref()
+        .and_then(|t| state.feed_tokens.user_for(t))
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid feed token"))
+}
+
+/// Absolute base URL of the forum as seen by the client, including the
+/// path it is mounted under. HTTP/2 clients name the host in the request
+/// URI rather than in a `Host` header.
+fn base_url(state: &AppState, headers: &HeaderMap, uri: &Uri) -> String {
+    let scheme = if state.https { "https" } else { "http" };
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| uri.authority().map(|a| a.as_str()))
+        .unwrap_or("localhost");
+    format!("{scheme}://{host}{}", state.base_path)
+}
+
+fn atom_response(xml: String) -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        xml,
+    )
+        .into_response()
+}
+
+/// Atom feed of recent posts across all categories.
+pub async fn forum_feed(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
+    Query(query): Query<FeedQuery>,
+) -> impl IntoResponse {
+    let user = match authorize_feed(&state, &query) {
+        Ok(user) => user,
+        Err(rejection) => return rejection.into_response(),
+    };
+    debug!(user = %redact(user), "rendering forum feed");
+
+    let db = state.db.read().await;
+    let mut items = Vec::new();
+    for category in categories(&db.root) {
+        collect_category_items(category, &mut items);
+    }
+    let items = most_recent(items, query.limit.unwrap_or(DEFAULT_FEED_LIMIT));
+
+    atom_response(render_atom(
+        &format!("urn:uuid:{}", db.root.uuid),
+        db.meta.database_name.as_deref().unwrap_or("kdbx-forum"),
+        &base_url(&state, &headers, &uri),
+        "/feed.atom",
+        &items,
+    ))
+}
+
+/// Atom feed of recent posts in one category.
+pub async fn category_feed(
+    State(state): State<AppState>,
+    Path(category_id): Path<String>,
+    headers: HeaderMap,
+    uri: Uri,
+    Query(query): Query<FeedQuery>,
+) -> impl IntoResponse {
+    let user = match authorize_feed(&state, &query) {
+        Ok(user) => user,
+        Err(rejection) => return rejection.into_response(),
+    };
+    debug!(category = %category_id, user = %redact(user), "rendering category feed");
+
+    let db = state.db.read().await;
+    let Some(category) = categories(&db.root).find(|g| g.uuid.to_string() == category_id) else {
+        return (StatusCode::NOT_FOUND, "Category not found").into_response();
+    };
+    let mut items = Vec::new();
+    collect_category_items(category, &mut items);
+    let items = most_recent(items, query.limit.unwrap_or(DEFAULT_FEED_LIMIT));
+
+    atom_response(render_atom(
+        &format!("urn:uuid:{}", category.uuid),
+        &category.name,
+        &base_url(&state, &headers, &uri),
+        &format!("/categories/{category_id}/feed.atom"),
+        &items,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    pub category: Option<String>,
+    pub thread: Option<String>,
+}
+
+/// Server-sent events stream of new threads and replies, optionally
+/// filtered to one category and/or thread.
+pub async fn events(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>> {
+    debug!(category = ?query.category, thread = ?query.thread, "subscribing to events");
+    let rx = state.events.subscribe();
+
+    let stream = stream::unfold((rx, query), |(mut rx, query)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if !event.matches(query.category.as_deref(), query.thread.as_deref()) {
+                        continue;
+                    }
+                    let sse = Event::default()
+                        .event(event.name())
+                        .json_data(&event)
+                        .unwrap_or_else(|_| Event::default().comment("serialization failed"));
+                    return Some((Ok(sse), (rx, query)));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    let sse = Event::default()
+                        .event("lagged")
+                        .data(skipped.to_string());
+                    return Some((Ok(sse), (rx, query)));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
 
-    (StatusCode::CREATED, reply_id).into_response()
+    Sse::new(stream).keep_alive(KeepAlive::default())
 }
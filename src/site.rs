@@ -0,0 +1,211 @@
+use std::{error::Error, fs, path::Path};
+
+use keepass::Database;
+
+use crate::{
+    attachments::{get_attachment, sanitize_file_name},
+    db::{category_dtos, find_entry_by_id, find_group_by_id, thread_detail, thread_summaries},
+    dto::PostDto,
+};
+
+const STYLE: &str =
+    "body { font-family: system-ui, sans-serif; max-width: 860px; margin: 2rem auto; }
+ul { list-style: none; padding-left: 0; }
+li { margin: 0.25rem 0; }
+a { color: #0366d6; text-decoration: none; }
+a:hover { text-decoration: underline; }
+.post { border-bottom: 1px solid #eee; padding: 0.5rem 0; }
+.post-author, .post-title { font-weight: 600; }
+.post-body { white-space: pre-wrap; margin-top: 0.25rem; }
+.muted { color: #666; font-size: 0.9rem; }
+.thumb { max-width: 160px; max-height: 120px; border: 1px solid #ddd; }";
+
+/// Escape text for use in HTML element content and attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encode a path segment for use in a relative link.
+fn encode_segment(segment: &str) -> String {
+    let mut out = String::new();
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+fn page(title: &str, css_path: &str, body: &str) -> String {
+    format!(
+        "<!doctype html>\n<html lang=\"en\">\n<head>\n  <meta charset=\"utf-8\">\n  \
+         <title>{}</title>\n  <link rel=\"stylesheet\" href=\"{css_path}\">\n</head>\n\
+         <body>\n{body}</body>\n</html>\n",
+        escape_html(title)
+    )
+}
+
+fn render_post(post: &PostDto) -> String {
+    let title = if post.title.is_empty() {
+        "(no title)"
+    } else {
+        &post.title
+    };
+    let author = if post.author.is_empty() {
+        "Anonymous"
+    } else {
+        &post.author
+    };
+    let mut html = format!(
+        "<div class=\"post\">\n  <div><span class=\"post-title\">{}</span> \
+         <span class=\"muted\">by</span> <span class=\"post-author\">{}</span></div>\n  \
+         <div class=\"post-body\">{}</div>\n",
+        escape_html(title),
+        escape_html(author),
+        escape_html(&post.body)
+    );
+    for att in &post.attachments {
+        let Some(file_name) = sanitize_file_name(&att.name) else {
+            continue;
+        };
+        let href = format!(
+            "../attachments/{}/{}",
+            encode_segment(&post.id),
+            encode_segment(&file_name)
+        );
+        if att.content_type.starts_with("image/") {
+            html.push_str(&format!(
+                "  <a href=\"{href}\"><img class=\"thumb\" src=\"{href}\" alt=\"{}\"></a>\n",
+                escape_html(&att.name)
+            ));
+        } else {
+            html.push_str(&format!(
+                "  <a href=\"{href}\">{} ({} bytes)</a>\n",
+                escape_html(&att.name),
+                att.size
+            ));
+        }
+    }
+    html.push_str("</div>\n");
+    html
+}
+
+/// Write a static, read-only HTML snapshot of the whole forum into `out_dir`.
+///
+/// Layout: `index.html`, `categories/<id>.html`, `threads/<id>.html`,
+/// `attachments/<post-id>/<name>` and a shared `style.css`, all linked
+/// relatively so the directory can be opened straight from disk.
+pub fn export_html_site(db: &Database, out_dir: &Path) -> Result<usize, Box<dyn Error>> {
+    fs::create_dir_all(out_dir.join("categories"))?;
+    fs::create_dir_all(out_dir.join("threads"))?;
+    fs::write(out_dir.join("style.css"), STYLE)?;
+
+    let forum_name = db
+        .meta
+        .database_name
+        .clone()
+        .unwrap_or_else(|| "kdbx-forum".to_string());
+    let categories = category_dtos(db);
+    let mut pages = 1;
+
+    let mut index = format!("<h1>{}</h1>\n<ul>\n", escape_html(&forum_name));
+    for cat in &categories {
+        index.push_str(&format!(
+            "  <li><a href=\"categories/{}.html\">{}</a></li>\n",
+            encode_segment(&cat.id),
+            escape_html(&cat.name)
+        ));
+    }
+    index.push_str("</ul>\n");
+    fs::write(
+        out_dir.join("index.html"),
+        page(&forum_name, "style.css", &index),
+    )?;
+
+    for cat in &categories {
+        let Some(category) = find_group_by_id(&db.root, &cat.id) else {
+            continue;
+        };
+        let threads = thread_summaries(category);
+
+        let mut body = format!(
+            "<p><a href=\"../index.html\">{}</a></p>\n<h1>{}</h1>\n<ul>\n",
+            escape_html(&forum_name),
+            escape_html(&cat.name)
+        );
+        for th in &threads {
+            body.push_str(&format!(
+                "  <li><a href=\"../threads/{}.html\">{}</a> <span class=\"muted\">({} posts)</span></li>\n",
+                encode_segment(&th.id),
+                escape_html(&th.title),
+                th.post_count
+            ));
+
+            let Some(thread_group) = find_group_by_id(category, &th.id) else {
+                continue;
+            };
+            let detail = thread_detail(thread_group, None, false);
+            let mut thread_body = format!(
+                "<p><a href=\"../index.html\">{}</a> / <a href=\"../categories/{}.html\">{}</a></p>\n<h1>{}</h1>\n",
+                escape_html(&forum_name),
+                encode_segment(&cat.id),
+                escape_html(&cat.name),
+                escape_html(&detail.title)
+            );
+            for post in &detail.posts {
+                thread_body.push_str(&render_post(post));
+                write_attachments(db, post, out_dir)?;
+            }
+            fs::write(
+                out_dir.join("threads").join(format!("{}.html", th.id)),
+                page(&detail.title, "../style.css", &thread_body),
+            )?;
+            pages += 1;
+        }
+        body.push_str("</ul>\n");
+
+        fs::write(
+            out_dir.join("categories").join(format!("{}.html", cat.id)),
+            page(&cat.name, "../style.css", &body),
+        )?;
+        pages += 1;
+    }
+
+    Ok(pages)
+}
+
+fn write_attachments(db: &Database, post: &PostDto, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+    if post.attachments.is_empty() {
+        return Ok(());
+    }
+    let Some(entry) = find_entry_by_id(&db.root, &post.id) else {
+        return Ok(());
+    };
+
+    let dir = out_dir.join("attachments").join(&post.id);
+    fs::create_dir_all(&dir)?;
+    for att in &post.attachments {
+        // Names that came in through `import` or KeePassXC were never
+        // sanitized, and must not reach outside the output directory.
+        let Some(file_name) = sanitize_file_name(&att.name) else {
+            continue;
+        };
+        if let Some((_, data)) = get_attachment(entry, &att.name) {
+            fs::write(dir.join(file_name), data)?;
+        }
+    }
+    Ok(())
+}
@@ -1,24 +1,207 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
 use keepass::{Database, DatabaseKey};
-use tokio::sync::RwLock;
+use tokio::{
+    sync::{broadcast, Notify, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    task::JoinHandle,
+};
+
+use crate::{
+    attachments::AttachmentLimits,
+    db::{file_modified, open_database, save_database},
+    dto::IntegrityReportDto,
+    events::{ForumEvent, EVENT_CHANNEL_CAPACITY},
+    feed::FeedTokens,
+    metrics::Metrics,
+    outbox::OutboundConfig,
+};
+
+/// How often a read-only instance checks whether the file has changed.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The decrypted database behind a lock whose wait times are recorded.
+pub struct DbLock {
+    lock: RwLock<Database>,
+    metrics: Arc<Metrics>,
+}
+
+impl DbLock {
+    pub async fn read(&self) -> RwLockReadGuard<'_, Database> {
+        let started = Instant::now();
+        let guard = self.lock.read().await;
+        self.metrics.observe_lock_wait("read", started.elapsed());
+        guard
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, Database> {
+        let started = Instant::now();
+        let guard = self.lock.write().await;
+        self.metrics.observe_lock_wait("write", started.elapsed());
+        guard
+    }
+}
 
 /// Shared application state, holding the decrypted KeePass database
 /// and the information needed to persist changes back to disk.
 #[derive(Clone)]
 pub struct AppState {
-    pub db: Arc<RwLock<Database>>,
+    pub db: Arc<DbLock>,
     pub db_path: PathBuf,
     pub key: DatabaseKey,
+    pub attachment_limits: AttachmentLimits,
+    pub feed_tokens: Arc<FeedTokens>,
+    pub events: broadcast::Sender<ForumEvent>,
+    pub outbound: Arc<OutboundConfig>,
+    /// Wakes the outbox worker when new deliveries are queued.
+    pub outbox_wake: Arc<Notify>,
+    /// Users allowed to moderate, as named in the user header.
+    pub moderators: Arc<BTreeSet<String>>,
+    pub metrics: Arc<Metrics>,
+    /// Error of the most recent save, `None` while saves succeed.
+    pub last_save_error: Arc<Mutex<Option<String>>>,
+    /// Serving reads only: nothing is saved and the file is re-read when it changes.
+    pub read_only: bool,
+    /// Modification time of the file when it was last read or written.
+    pub disk_modified: Arc<Mutex<Option<SystemTime>>>,
+    /// Where the forum's routes are mounted: empty at the root, `/f/<name>`
+    /// on a server hosting several forums.
+    pub base_path: String,
+    /// Served over TLS, so absolute links use `https`.
+    pub https: bool,
+    /// Set once the forum has been locked again; nothing may be saved after.
+    pub closed: Arc<AtomicBool>,
+    /// The last integrity report and when it was made. Checks take turns
+    /// on this lock, as each one derives the key again.
+    pub integrity_report: Arc<tokio::sync::Mutex<Option<(Instant, IntegrityReportDto)>>>,
 }
 
 impl AppState {
-    pub fn new(db: Database, db_path: PathBuf, key: DatabaseKey) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        db: Database,
+        db_path: PathBuf,
+        key: DatabaseKey,
+        attachment_limits: AttachmentLimits,
+        feed_tokens: FeedTokens,
+        outbound: OutboundConfig,
+        moderators: BTreeSet<String>,
+        read_only: bool,
+        base_path: String,
+        https: bool,
+    ) -> Self {
+        let metrics = Arc::new(Metrics::default());
+        let disk_modified = file_modified(&db_path);
         Self {
-            db: Arc::new(RwLock::new(db)),
+            db: Arc::new(DbLock {
+                lock: RwLock::new(db),
+                metrics: metrics.clone(),
+            }),
             db_path,
             key,
+            attachment_limits,
+            feed_tokens: Arc::new(feed_tokens),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            outbound: Arc::new(outbound),
+            outbox_wake: Arc::new(Notify::new()),
+            moderators: Arc::new(moderators),
+            metrics,
+            last_save_error: Arc::new(Mutex::new(None)),
+            read_only,
+            disk_modified: Arc::new(Mutex::new(disk_modified)),
+            base_path,
+            https,
+            closed: Arc::new(AtomicBool::new(false)),
+            integrity_report: Arc::default(),
+        }
+    }
+
+    /// Whether `user` is one of the configured moderators.
+    pub fn is_moderator(&self, user: Option<&str>) -> bool {
+        user.is_some_and(|u| self.moderators.contains(u))
+    }
+
+    /// Write the database back to its file, recording how long that took.
+    ///
+    /// The lock file keeps other kdbx-forum processes away, but not KeePassXC
+    /// or a restored backup, so a file that changed since it was loaded is
+    /// left alone rather than silently overwritten.
+    pub fn save(&self, db: &Database) -> Result<(), Box<dyn Error>> {
+        if self.read_only {
+            return Err("This instance is read-only".into());
+        }
+        if self.closed.load(Ordering::Relaxed) {
+            return Err("The forum has been locked".into());
+        }
+        let started = Instant::now();
+        let mut known = self
+            .disk_modified
+            .lock()
+            .map_err(|_| "Save state is poisoned")?;
+        let result = if known.is_some() && file_modified(&self.db_path) != *known {
+            Err(format!(
+                "{} was changed by another program since it was loaded; restart to pick up \
+                 those changes",
+                self.db_path.display()
+            )
+            .into())
+        } else {
+            save_database(db, &self.db_path, &self.key)
+        };
+        if result.is_ok() {
+            *known = file_modified(&self.db_path);
+        }
+        drop(known);
+        self.metrics.observe_save(started.elapsed(), result.is_ok());
+        if let Ok(mut last) = self.last_save_error.lock() {
+            *last = result.as_ref().err().map(|e| e.to_string());
         }
+        result
+    }
+
+    /// Publish a change to live subscribers. Having no subscribers is not an error.
+    pub fn publish(&self, event: ForumEvent) {
+        let _ = self.events.send(event);
     }
 }
 
+/// On a read-only instance, re-read the file whenever its modification time
+/// changes, so posts saved by the writing instance show up. A file that does
+/// not open (e.g. caught halfway through a copy) is retried on the next tick.
+pub fn spawn_file_watcher(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+            let modified = file_modified(&state.db_path);
+            let known = state.disk_modified.lock().map(|m| *m).unwrap_or_default();
+            if modified.is_none() || modified == known {
+                continue;
+            }
+
+            let (path, key) = (state.db_path.clone(), state.key.clone());
+            let opened = tokio::task::spawn_blocking(move || {
+                open_database(&path, &key).map_err(|e| e.to_string())
+            })
+            .await;
+            match opened {
+                Ok(Ok(db)) => {
+                    *state.db.write().await = db;
+                    if let Ok(mut known) = state.disk_modified.lock() {
+                        *known = modified;
+                    }
+                    tracing::info!("Reloaded the database after it changed on disk");
+                }
+                Ok(Err(e)) => tracing::warn!(error = %e, "Failed to reload the database"),
+                Err(e) => tracing::warn!(error = %e, "Failed to reload the database"),
+            }
+        }
+    })
+}
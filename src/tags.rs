@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use keepass::db::{Entry, Group, Node, NodeRef, Times};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    db::{categories, thread_summaries},
+    dto::{TagCountDto, TaggedThreadDto},
+    moderation::is_published,
+};
+
+/// Comma-separated tags custom attribute, as suggested in `prompt.md`.
+/// Read for compatibility; tags are written to the entry's native Tags.
+pub const TAGS_FIELD: &str = "tags";
+
+const MAX_TAGS_PER_THREAD: usize = 10;
+const MAX_TAG_LENGTH: usize = 32;
+
+/// Split user input like `"rust, kdbx;help"` into trimmed, non-empty tags.
+pub fn parse_tag_list(input: &str) -> Vec<String> {
+    input
+        .split([',', ';'])
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Deserialize tags given either as a list or as one comma-separated string
+/// (the latter is what multipart forms send).
+pub fn deserialize_tags<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        List(Vec<String>),
+        Text(String),
+    }
+    Ok(match Tags::deserialize(deserializer)? {
+        Tags::List(list) => list.iter().flat_map(|t| parse_tag_list(t)).collect(),
+        Tags::Text(text) => parse_tag_list(&text),
+    })
+}
+
+/// Trim, validate and de-duplicate (case-insensitively) a list of tags.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut out: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            continue;
+        }
+        if tag.contains([',', ';']) {
+            return Err(format!("Tag '{tag}' must not contain ',' or ';'"));
+        }
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(format!("Tag '{tag}' is longer than {MAX_TAG_LENGTH} characters"));
+        }
+        if !out.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            out.push(tag.to_string());
+        }
+    }
+    if out.len() > MAX_TAGS_PER_THREAD {
+        return Err(format!("A thread can have at most {MAX_TAGS_PER_THREAD} tags"));
+    }
+    Ok(out)
+}
+
+/// Tags of a post: its native Tags plus the `tags` custom attribute.
+fn entry_tags(entry: &Entry) -> Vec<String> {
+    let mut tags = entry.tags.clone();
+    if let Some(field) = entry.get(TAGS_FIELD) {
+        tags.extend(parse_tag_list(field));
+    }
+    normalize_tags(&tags).unwrap_or_else(|_| {
+        // Tags set outside the forum may break our limits; show them anyway.
+        let mut tags: Vec<String> = tags.iter().map(|t| t.trim().to_string()).collect();
+        tags.retain(|t| !t.is_empty());
+        tags.dedup();
+        tags
+    })
+}
+
+/// Tags of a thread, which live on its opening post.
+pub fn thread_tags(thread: &Group) -> Vec<String> {
+    thread
+        .entries()
+        .first()
+        .map(|e| entry_tags(e))
+        .unwrap_or_default()
+}
+
+/// Whether `tags` include every one of `wanted` (compared case-insensitively).
+pub fn matches_tags(tags: &[String], wanted: &[String]) -> bool {
+    wanted
+        .iter()
+        .all(|w| tags.iter().any(|t| t.eq_ignore_ascii_case(w)))
+}
+
+/// Replace the tags of a thread. Returns the stored tags.
+pub fn set_thread_tags(thread: &mut Group, tags: &[String]) -> Result<Vec<String>, String> {
+    let tags = normalize_tags(tags)?;
+    let entry = thread
+        .children
+        .iter_mut()
+        .find_map(|node| match node {
+            Node::Entry(e) => Some(e),
+            Node::Group(_) => None,
+        })
+        .ok_or_else(|| "Thread has no posts".to_string())?;
+
+    entry.tags = tags.clone();
+    entry.fields.remove(TAGS_FIELD);
+    entry.times.set_last_modification(Times::now());
+    Ok(tags)
+}
+
+/// Number of threads per tag across all categories, most used first.
+pub fn tag_counts(root: &Group) -> Vec<TagCountDto> {
+    let mut counts: BTreeMap<String, (String, usize)> = BTreeMap::new();
+    for category in categories(root) {
+        for node in &category.children {
+            if let NodeRef::Group(thread) = node.as_ref()
+                && thread.entries().first().is_none_or(|e| is_published(e))
+            {
+                for tag in thread_tags(thread) {
+                    counts.entry(tag.to_ascii_lowercase()).or_insert((tag, 0)).1 += 1;
+                }
+            }
+        }
+    }
+
+    let mut out: Vec<TagCountDto> = counts
+        .into_values()
+        .map(|(tag, count)| TagCountDto { tag, count })
+        .collect();
+    out.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    out
+}
+
+/// All threads carrying `tag`, across categories.
+pub fn threads_with_tag(root: &Group, tag: &str) -> Vec<TaggedThreadDto> {
+    let wanted = [tag.to_string()];
+    let mut out = Vec::new();
+    for category in categories(root) {
+        for thread in thread_summaries(category) {
+            if matches_tags(&thread.tags, &wanted) {
+                out.push(TaggedThreadDto {
+                    category_id: category.uuid.to_string(),
+                    category_name: category.name.clone(),
+                    thread,
+                });
+            }
+        }
+    }
+    out
+}
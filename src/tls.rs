@@ -0,0 +1,187 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::Request,
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Redirect},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where the server's certificate, key and (for mTLS) client CA live.
+#[derive(Clone, Debug)]
+pub struct TlsOptions {
+    /// PEM certificate chain, leaf first.
+    pub cert: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key: PathBuf,
+    /// PEM bundle of CAs that client certificates must chain to. Without
+    /// it, clients are not asked for a certificate.
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsOptions {
+    fn files(&self) -> impl Iterator<Item = &Path> {
+        [&self.cert, &self.key]
+            .into_iter()
+            .chain(self.client_ca.as_ref())
+            .map(PathBuf::as_path)
+    }
+
+    /// Newest modification time of the files, to notice replaced certificates.
+    fn modified(&self) -> Option<SystemTime> {
+        self.files()
+            .filter_map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+            .max()
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificates from {}: {e}", path.display()))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Build the rustls configuration from the files named in `options`.
+pub fn load_server_config(options: &TlsOptions) -> Result<ServerConfig, String> {
+    let certs = read_certs(&options.cert)?;
+    let key = PrivateKeyDer::from_pem_file(&options.key).map_err(|e| {
+        format!("Failed to read private key from {}: {e}", options.key.display())
+    })?;
+
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {e}"))?;
+    let builder = match &options.client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("Invalid CA certificate in {}: {e}", ca.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots.into(), provider())
+                .build()
+                .map_err(|e| format!("Failed to set up client certificates: {e}"))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Certificate and key do not fit together: {e}"))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Reload the certificate, key and client CA when one of the files changes,
+/// or right away on SIGHUP. A broken replacement is logged and the current
+/// configuration kept, so renewing a certificate never takes the server down.
+pub fn spawn_reloader(config: RustlsConfig, options: TlsOptions) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok();
+        let mut loaded_at = options.modified();
+        loop {
+            #[cfg(unix)]
+            let forced = match hangup.as_mut() {
+                Some(hangup) => tokio::select! {
+                    _ = hangup.recv() => true,
+                    _ = tokio::time::sleep(RELOAD_INTERVAL) => false,
+                },
+                None => {
+                    tokio::time::sleep(RELOAD_INTERVAL).await;
+                    false
+                }
+            };
+            #[cfg(not(unix))]
+            let forced = {
+                tokio::time::sleep(RELOAD_INTERVAL).await;
+                false
+            };
+
+            let modified = options.modified();
+            if !forced && modified == loaded_at {
+                continue;
+            }
+            match load_server_config(&options) {
+                Ok(new) => {
+                    config.reload_from_config(Arc::new(new));
+                    loaded_at = modified;
+                    tracing::info!("Reloaded TLS certificate");
+                }
+                Err(e) => tracing::error!(error = %e, "Keeping the current TLS certificate"),
+            }
+        }
+    });
+}
+
+/// Plain HTTP app that sends every request to the same path over HTTPS on
+/// `https_port`.
+pub fn redirect_app(https_port: u16) -> Router {
+    Router::new().fallback(move |request: Request| async move {
+        let Some(host) = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse::<axum::http::uri::Authority>().ok())
+        else {
+            return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+        };
+        let authority = match https_port {
+            443 => host.host().to_string(),
+            port => format!("{}:{port}", host.host()),
+        };
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |p| p.as_str());
+        match Uri::builder()
+            .scheme("https")
+            .authority(authority)
+            .path_and_query(path)
+            .build()
+        {
+            Ok(uri) => Redirect::permanent(&uri.to_string()).into_response(),
+            Err(_) => (StatusCode::BAD_REQUEST, "Invalid Host header").into_response(),
+        }
+    })
+}
+
+/// Serve `redirect_app` on `listen` in the background.
+pub async fn spawn_redirect_listener(listen: &str, https: SocketAddr) -> Result<(), String> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| format!("Failed to bind redirect listener on {listen}: {e}"))?;
+    tracing::info!("Redirecting http://{listen} to HTTPS");
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, redirect_app(https.port())).await {
+            tracing::error!(error = %e, "Redirect listener stopped");
+        }
+    });
+    Ok(())
+}